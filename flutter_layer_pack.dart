@@ -14,6 +14,7 @@ class LayerPack {
 
   late ffi.DynamicLibrary _lib;
   late int Function(ffi.Pointer<Utf8>, ffi.Pointer<Utf8>, ffi.Pointer<Utf8>) _unpackFiles;
+  late int Function(ffi.Pointer<Utf8>, ffi.Pointer<Utf8>, ffi.Pointer<Utf8>, ffi.Pointer<Utf8>) _unpackFilesWithKey;
 
   // รหัสลับ (แนะนำให้ทำ Obfuscation ในขั้นตอน build)
   static const String _secretKey = "LAYER_PACK_SECRET_2026";
@@ -26,6 +27,10 @@ class LayerPack {
         .lookup<ffi.NativeFunction<ffi.Int32 Function(
             ffi.Pointer<Utf8>, ffi.Pointer<Utf8>, ffi.Pointer<Utf8>)>>('ffi_unpack_files')
         .asFunction();
+    _unpackFilesWithKey = _lib
+        .lookup<ffi.NativeFunction<ffi.Int32 Function(
+            ffi.Pointer<Utf8>, ffi.Pointer<Utf8>, ffi.Pointer<Utf8>, ffi.Pointer<Utf8>)>>('ffi_unpack_files_with_key')
+        .asFunction();
   }
 
   ffi.DynamicLibrary _loadLibrary() {
@@ -52,13 +57,18 @@ class LayerPack {
   /// ฟังก์ชันแตกไฟล์หลัก
   /// [packPath]: ที่อยู่ไฟล์ .lpack
   /// [outputPath]: โฟลเดอร์ปลายทาง
+  /// [decryptKey]: รหัสถอดไฟล์ที่เข้ารหัส (ถ้าไม่ระบุ ต้อง build ด้วย `--features embedded-key`)
   /// คืนค่า 0 หากสำเร็จ, -99 หาก Key ผิด, และค่าติดลบอื่นๆ ตาม Error ใน Rust
-  int unpack(String packPath, String outputPath) {
+  int unpack(String packPath, String outputPath, {String? decryptKey}) {
     final pKey = _secretKey.toNativeUtf8();
     final pPack = packPath.toNativeUtf8();
     final pOut = outputPath.toNativeUtf8();
+    final pDecrypt = decryptKey?.toNativeUtf8();
     
     try {
+      if (pDecrypt != null) {
+        return _unpackFilesWithKey(pKey, pPack, pOut, pDecrypt);
+      }
       return _unpackFiles(pKey, pPack, pOut);
     } catch (e) {
       // จัดการ Error กรณีเรียก Native ไม่ได้
@@ -68,6 +78,7 @@ class LayerPack {
       malloc.free(pKey);
       malloc.free(pPack);
       malloc.free(pOut);
+      if (pDecrypt != null) malloc.free(pDecrypt);
     }
   }
 }
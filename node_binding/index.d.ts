@@ -4,7 +4,7 @@
 /* auto-generated by NAPI-RS */
 
 export declare class JsLayerPack {
-  constructor(path: string, key: string)
+  constructor(path: string, key: string, decryptionKey?: string | undefined | null)
   get name(): string
   get author(): string | null
   getFileList(): Array<string>
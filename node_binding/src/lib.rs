@@ -2,6 +2,12 @@ use napi_derive::napi;
 use napi::{Result, Error, Status};
 use napi::bindgen_prelude::Buffer;
 use layer_pack::resolver::LoadedPack;
+use layer_pack::LayerPackError;
+
+// แปลง error ของ core ให้ฝั่ง JS อ่าน `err.code` ได้ (เช่น "LPACK_NOT_FOUND")
+fn to_js_error(context: &str, e: LayerPackError) -> Error<String> {
+    Error::new(e.code_name().to_string(), format!("{}: {}", context, e))
+}
 
 // ประกาศ struct ให้ JS เรียกใช้ได้
 #[napi]
@@ -16,15 +22,19 @@ const SECURITY_KEY: &str = env!("LPACK_SECURITY_KEY");
 #[napi]
 impl JsLayerPack {
     // Constructor: โหลดไฟล์ pack
+    // decryption_key: ถ้าไม่ส่งมาจะใช้คีย์ที่ฝังไว้ตอน build
     #[napi(constructor)]
-    pub fn new(path: String, key: String) -> Result<Self> {
+    pub fn new(path: String, key: String, decryption_key: Option<String>) -> Result<Self, String> {
         // [SECURITY] ตรวจสอบรหัสลับป้องกันการนำไปใช้ผิด
         if key != SECURITY_KEY {
-             return Err(Error::new(Status::InvalidArg, "Invalid security key".to_string()));
+             return Err(Error::new(Status::InvalidArg.as_ref().to_string(), "Invalid security key".to_string()));
         }
 
-        let pack = LoadedPack::load(&path)
-            .map_err(|e| Error::new(Status::GenericFailure, format!("Failed to load pack: {}", e)))?;
+        let pack = match decryption_key {
+            Some(secret) => LoadedPack::load_with_key(&path, secret.as_bytes()),
+            None => LoadedPack::load(&path),
+        }
+        .map_err(|e| to_js_error("Failed to load pack", e))?;
         
         Ok(JsLayerPack { inner: pack })
     }
@@ -49,9 +59,9 @@ impl JsLayerPack {
 
     // Method: อ่านไฟล์ออกมาเป็น Buffer
     #[napi]
-    pub fn read_file(&mut self, path: String) -> Result<Buffer> {
+    pub fn read_file(&self, path: String) -> Result<Buffer, String> {
         let data = self.inner.read_file(&path)
-            .map_err(|e| Error::new(Status::GenericFailure, format!("Failed to read file: {}", e)))?;
+            .map_err(|e| to_js_error("Failed to read file", e))?;
         Ok(data.into())
     }
 }
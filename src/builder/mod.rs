@@ -1,17 +1,125 @@
+use std::collections::HashSet;
 use std::fs::File;
-use std::io::{Read, Write, Seek, SeekFrom};
+use std::io::{self, BufReader, Read, Write, Seek, SeekFrom};
 use std::path::Path;
 use walkdir::WalkDir;
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use sha2::{Sha256, Digest};
-use crate::format::{PackManifest, FileEntry, CompressionType, PackType};
+use crate::crypto;
+use crate::format::{
+    PackManifest, FileEntry, EntryKind, CompressionType, EncryptionType, EncryptionInfo, Kdf, PackType,
+    MAGIC, FORMAT_VERSION,
+};
+pub use crate::format::{DELETE_LIST, MERGE_PATCH_SUFFIX};
+use crate::format::chunked::{BlockTable, DEFAULT_BLOCK_SIZE, MAX_BLOCK_SIZE};
+use crate::format::index::encode_index;
+use crate::format::path as pack_path;
+use crate::format::trailer::{auth_digest, encode_trailer, AuthTag, PackSignature, Trailer};
+
+/// Key settings recorded in the manifest of an encrypted pack.
+pub struct EncryptionOptions {
+    /// Label readers use to pick the secret (see `crypto::KeyRing`).
+    pub key_id: Option<String>,
+    pub kdf: Kdf,
+}
+
+impl Default for EncryptionOptions {
+    /// No key ID, HKDF-SHA256 with a fresh salt.
+    fn default() -> Self {
+        Self { key_id: None, kdf: crypto::new_hkdf() }
+    }
+}
+
+/// Read buffer size for streamed entries.
+const STREAM_CHUNK: usize = 1024 * 1024;
+
+struct EncryptionConfig {
+    key: [u8; 32],
+    /// `None` encrypts every file.
+    filter: Option<GlobSet>,
+}
 
 pub struct PackBuilder {
     manifest: PackManifest,
+    encryption: Option<EncryptionConfig>,
+    /// Ed25519 seed used to sign the pack.
+    signing_seed: Option<[u8; 32]>,
+    /// Block size for `ZstdChunked` entries; `None` keeps Zstd entries whole.
+    chunk_size: Option<u32>,
+    /// Paths written as tombstones, in addition to the `DELETE_LIST` file.
+    deletions: Vec<String>,
 }
 
 impl PackBuilder {
     pub fn new(manifest: PackManifest) -> Self {
-        Self { manifest }
+        Self { manifest, encryption: None, signing_seed: None, chunk_size: None, deletions: Vec::new() }
+    }
+
+    /// Write tombstones for `paths`, so a `Resolver` treats them as missing
+    /// even when a lower-priority pack has them. Same as listing them in the
+    /// source directory's `.lpack-delete` file.
+    pub fn with_deletions<I, S>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.deletions.extend(paths.into_iter().map(Into::into));
+        self
+    }
+
+    /// Write Zstd entries larger than `block_size` as `ZstdChunked`, so readers
+    /// can decode a byte range without the rest of the file. Without this call
+    /// only encrypted entries are chunked, in blocks of `DEFAULT_BLOCK_SIZE`.
+    pub fn with_chunking(mut self, block_size: u32) -> anyhow::Result<Self> {
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+            anyhow::bail!("Block size must be between 1 and {} bytes", MAX_BLOCK_SIZE);
+        }
+        self.chunk_size = Some(block_size);
+        Ok(self)
+    }
+
+    /// Sign the pack with an Ed25519 key (its 32-byte seed). The signature
+    /// covers the header, manifest and index, which in turn hold every
+    /// entry's hash.
+    pub fn with_signing_key(mut self, seed: [u8; 32]) -> Self {
+        self.signing_seed = Some(seed);
+        self
+    }
+
+    /// Encrypt entries with AES-256-GCM under a key derived from `secret`. With
+    /// no `patterns` every file is encrypted, otherwise only paths matching one
+    /// of the globs (matched against the pack-relative path, e.g. `data/**/*.json`).
+    pub fn with_encryption(
+        mut self,
+        secret: &[u8],
+        patterns: &[String],
+        options: EncryptionOptions,
+    ) -> anyhow::Result<Self> {
+        let filter = if patterns.is_empty() {
+            None
+        } else {
+            let mut set = GlobSetBuilder::new();
+            for pattern in patterns {
+                set.add(Glob::new(pattern)?);
+            }
+            Some(set.build()?)
+        };
+
+        let key = crypto::derive_key_with(&options.kdf, secret)?;
+        self.manifest.encryption = Some(EncryptionInfo {
+            key_id: options.key_id,
+            kdf: options.kdf,
+        });
+        self.encryption = Some(EncryptionConfig { key, filter });
+        Ok(self)
+    }
+
+    fn should_encrypt(&self, rel_path: &str) -> Option<&[u8; 32]> {
+        let config = self.encryption.as_ref()?;
+        match &config.filter {
+            Some(filter) if !filter.is_match(rel_path) => None,
+            _ => Some(&config.key),
+        }
     }
 
     pub fn build<P: AsRef<Path>>(&self, source_dir: P, output_file: P) -> anyhow::Result<()> {
@@ -19,8 +127,8 @@ impl PackBuilder {
         let mut out = File::create(output_file)?;
         
         // 1. Write Header Magic
-        out.write_all(b"LPACK")?;
-        out.write_all(&1u32.to_le_bytes())?;
+        out.write_all(MAGIC)?;
+        out.write_all(&FORMAT_VERSION.to_le_bytes())?;
 
         // 2. Prepare Manifest
         let manifest_json = serde_json::to_vec(&self.manifest)?;
@@ -44,48 +152,71 @@ impl PackBuilder {
         for entry in WalkDir::new(source_dir).into_iter().filter_map(|e| e.ok()) {
             if entry.file_type().is_file() {
                 let path = entry.path();
-                let rel_path = path.strip_prefix(source_dir)?.to_string_lossy().replace("\\", "/");
+                let rel_path = pack_path::normalize(&path.strip_prefix(source_dir)?.to_string_lossy()).into_owned();
                 
                 // Skip hidden files or config files if needed
                 if rel_path.starts_with(".") || rel_path == "pack.json" {
                     continue;
                 }
 
-                let mut file = File::open(path)?;
-                let mut content = Vec::new();
-                file.read_to_end(&mut content)?;
-                
-                // Calculate Hash
-                let mut hasher = Sha256::new();
-                hasher.update(&content);
-                let hash = hex::encode(hasher.finalize());
-
-                // Select Compression
-                let (compressed_data, compression) = self.compress_data(&content, &rel_path);
+                let (rel_path, kind) = match rel_path.strip_suffix(MERGE_PATCH_SUFFIX) {
+                    Some(target) => {
+                        let patch: serde_json::Value = serde_json::from_reader(BufReader::new(File::open(path)?))
+                            .map_err(|e| anyhow::anyhow!("'{}' is not a valid merge patch: {}", rel_path, e))?;
+                        if !patch.is_object() {
+                            anyhow::bail!("'{}' must be a JSON object to be used as a merge patch", rel_path);
+                        }
+                        (target.to_string(), EntryKind::MergePatch)
+                    }
+                    None => (rel_path, EntryKind::File),
+                };
 
-                let offset = out.stream_position()?;
-                out.write_all(&compressed_data)?;
-                
-                entries.push(FileEntry {
-                    path: rel_path,
-                    offset,
-                    original_size: content.len() as u64,
-                    compressed_size: compressed_data.len() as u64,
-                    compression,
-                    encryption: crate::format::EncryptionType::None,
-                    hash,
-                });
+                let mut entry = match self.should_encrypt(&rel_path) {
+                    Some(key) => self.write_encrypted(&mut out, path, rel_path, key)?,
+                    None => self.write_streamed(&mut out, path, rel_path)?,
+                };
+                entry.kind = kind;
+                entries.push(entry);
             }
         }
 
+        let mut seen = HashSet::new();
+        if let Some(entry) = entries.iter().find(|e| !seen.insert(e.path.as_str())) {
+            anyhow::bail!("'{}' is both packed and patched", entry.path);
+        }
+
         let data_end_pos = out.stream_position()?;
 
+        // Tombstones carry no data, only an index record
+        let mut deletions = read_delete_list(source_dir)?;
+        deletions.extend(self.deletions.iter().map(|p| pack_path::normalize(p).into_owned()));
+        deletions.sort();
+        deletions.dedup();
+        for path in deletions {
+            if entries.iter().any(|e: &FileEntry| e.path == path) {
+                anyhow::bail!("'{}' is both packed and listed for deletion", path);
+            }
+            entries.push(FileEntry::tombstone(path));
+        }
+
         // 4. Write Index
-        let index_json = serde_json::to_vec(&entries)?;
-        out.write_all(&index_json)?;
-        let index_len = index_json.len();
+        let index_bytes = encode_index(&entries)?;
+        out.write_all(&index_bytes)?;
+        let index_len = index_bytes.len();
 
-        // 5. Update Index Pointers
+        // 5. Write Trailer: encrypted packs get a MAC under the pack key, others a plain checksum
+        let digest = auth_digest(FORMAT_VERSION, &manifest_json, data_end_pos, &index_bytes);
+        let auth = match &self.encryption {
+            Some(config) => AuthTag::HmacSha256(crypto::pack_mac(&config.key, &digest)),
+            None => AuthTag::Sha256(digest),
+        };
+        let signature = self.signing_seed.as_ref().map(|seed| PackSignature {
+            public_key: crypto::signing_public_key(seed),
+            signature: crypto::sign_pack(seed, &digest),
+        });
+        out.write_all(&encode_trailer(&Trailer { auth: Some(auth), signature }))?;
+
+        // 6. Update Index Pointers
         out.seek(SeekFrom::Start(index_ptr_pos))?;
         out.write_all(&data_end_pos.to_le_bytes())?;
         out.write_all(&(index_len as u32).to_le_bytes())?;
@@ -93,47 +224,228 @@ impl PackBuilder {
         Ok(())
     }
 
-    fn compress_data(&self, data: &[u8], path: &str) -> (Vec<u8>, CompressionType) {
+    /// Which compression to try for `path`; the result falls back to `Store`
+    /// when compressing does not make the entry smaller.
+    fn choose_compression(&self, path: &str) -> CompressionType {
         // Simple heuristic
         let ext = Path::new(path).extension().and_then(|s| s.to_str()).unwrap_or("");
-        
+
         match self.manifest.pack_type {
-            PackType::Text => {
-                // Always try Zstd for text pack
-                let compressed = zstd::stream::encode_all(std::io::Cursor::new(data), 3).unwrap_or(data.to_vec());
-                if compressed.len() < data.len() {
-                    (compressed, CompressionType::Zstd)
-                } else {
-                    (data.to_vec(), CompressionType::Store)
-                }
+            // Always try Zstd for text pack
+            PackType::Text => CompressionType::Zstd,
+            // Scripts use LZ4
+            PackType::Script => match ext {
+                "lua" | "js" | "py" => CompressionType::Lz4,
+                _ => CompressionType::Store,
             },
-            PackType::Script => {
-                // Scripts use LZ4
-                if ext == "lua" || ext == "js" || ext == "py" {
-                     let compressed = lz4_flex::compress_prepend_size(data);
-                     if compressed.len() < data.len() {
-                        (compressed, CompressionType::Lz4)
-                     } else {
-                        (data.to_vec(), CompressionType::Store)
-                     }
-                } else {
-                    (data.to_vec(), CompressionType::Store)
-                }
+            // Default heuristic
+            _ => match ext {
+                "txt" | "json" | "xml" | "yaml" | "csv" => CompressionType::Zstd,
+                _ => CompressionType::Store,
             },
-            _ => {
-                // Default heuristic
-                 match ext {
-                    "txt" | "json" | "xml" | "yaml" | "csv" => {
-                        let compressed = zstd::stream::encode_all(std::io::Cursor::new(data), 3).unwrap_or(data.to_vec());
-                        if compressed.len() < data.len() {
-                            (compressed, CompressionType::Zstd)
-                        } else {
-                            (data.to_vec(), CompressionType::Store)
-                        }
-                    },
-                    _ => (data.to_vec(), CompressionType::Store),
-                }
+        }
+    }
+
+    /// Streams `path` into `out` at the current position, hashing and
+    /// compressing in chunks so memory use does not depend on the file size.
+    /// LZ4 uses the frame format here, since the size-prepended block format
+    /// needs the whole input up front.
+    fn write_streamed(&self, out: &mut File, path: &Path, rel_path: String) -> anyhow::Result<FileEntry> {
+        let offset = out.stream_position()?;
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut source = HashingReader::new(BufReader::with_capacity(STREAM_CHUNK, file));
+
+        let mut compression = match self.choose_compression(&rel_path) {
+            CompressionType::Zstd if self.chunk_size.is_some_and(|bs| size > bs as u64) => {
+                let block_size = self.chunk_size.unwrap_or_default();
+                write_chunked(out, &mut source, size, block_size, None)?;
+                CompressionType::ZstdChunked
+            },
+            CompressionType::Zstd | CompressionType::ZstdChunked => {
+                let mut encoder = zstd::stream::Encoder::new(&mut *out, 3)?;
+                io::copy(&mut source, &mut encoder)?;
+                encoder.finish()?;
+                CompressionType::Zstd
+            },
+            CompressionType::Lz4 | CompressionType::Lz4Frame => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(&mut *out);
+                io::copy(&mut source, &mut encoder)?;
+                encoder.finish()?;
+                CompressionType::Lz4Frame
+            },
+            CompressionType::Store => {
+                io::copy(&mut source, out)?;
+                CompressionType::Store
+            },
+        };
+        let (original_size, hash) = source.finish();
+        let mut compressed_size = out.stream_position()? - offset;
+
+        // Not worth it: rewrite the entry uncompressed and drop the longer tail
+        if compression != CompressionType::Store && compressed_size >= original_size {
+            out.seek(SeekFrom::Start(offset))?;
+            let copied = io::copy(&mut File::open(path)?.take(original_size), out)?;
+            if copied != original_size {
+                anyhow::bail!("'{}' changed size while it was being packed", rel_path);
             }
+            out.set_len(offset + original_size)?;
+            compression = CompressionType::Store;
+            compressed_size = original_size;
+        }
+
+        Ok(FileEntry {
+            path: rel_path,
+            offset,
+            original_size,
+            compressed_size,
+            compression,
+            encryption: EncryptionType::None,
+            hash,
+            kind: EntryKind::File,
+        })
+    }
+
+    /// Files larger than one block are written as `ZstdChunked` with every
+    /// block sealed on its own, so only one block is held at a time. Smaller
+    /// files are sealed as one message after the usual compression.
+    fn write_encrypted(&self, out: &mut File, path: &Path, rel_path: String, key: &[u8; 32]) -> anyhow::Result<FileEntry> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let block_size = self.chunk_size.unwrap_or(DEFAULT_BLOCK_SIZE);
+        if size > block_size as u64 {
+            let offset = out.stream_position()?;
+            let mut source = HashingReader::new(BufReader::with_capacity(STREAM_CHUNK, file));
+            write_chunked(out, &mut source, size, block_size, Some((key, &rel_path)))?;
+            let (original_size, hash) = source.finish();
+            return Ok(FileEntry {
+                path: rel_path,
+                offset,
+                original_size,
+                compressed_size: out.stream_position()? - offset,
+                compression: CompressionType::ZstdChunked,
+                encryption: EncryptionType::Aes256Gcm,
+                hash,
+                kind: EntryKind::File,
+            });
+        }
+
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        let hash = hex::encode(Sha256::digest(&content));
+
+        // Encrypt after compressing; the reader decrypts first, then decompresses
+        let (compressed_data, compression) = self.compress_data(&content, &rel_path);
+        let stored_data = crypto::encrypt(key, &compressed_data, &crypto::entry_aad(&rel_path, None))?;
+
+        let offset = out.stream_position()?;
+        out.write_all(&stored_data)?;
+
+        Ok(FileEntry {
+            path: rel_path,
+            offset,
+            original_size: content.len() as u64,
+            compressed_size: stored_data.len() as u64,
+            compression,
+            encryption: EncryptionType::Aes256Gcm,
+            hash,
+            kind: EntryKind::File,
+        })
+    }
+
+    fn compress_data(&self, data: &[u8], path: &str) -> (Vec<u8>, CompressionType) {
+        let compressed = match self.choose_compression(path) {
+            CompressionType::Zstd | CompressionType::ZstdChunked => {
+                zstd::stream::encode_all(data, 3).ok().map(|c| (c, CompressionType::Zstd))
+            },
+            CompressionType::Lz4 | CompressionType::Lz4Frame => {
+                Some((lz4_flex::compress_prepend_size(data), CompressionType::Lz4))
+            },
+            CompressionType::Store => None,
+        };
+        match compressed {
+            Some((compressed, method)) if compressed.len() < data.len() => (compressed, method),
+            _ => (data.to_vec(), CompressionType::Store),
+        }
+    }
+}
+
+/// Reads `DELETE_LIST` from `source_dir`; no file means no deletions.
+fn read_delete_list(source_dir: &Path) -> anyhow::Result<Vec<String>> {
+    let list = match std::fs::read_to_string(source_dir.join(DELETE_LIST)) {
+        Ok(list) => list,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| pack_path::normalize(line).into_owned())
+        .collect())
+}
+
+/// Writes `size` bytes from `source` as a `ZstdChunked` payload. With `seal`,
+/// each block is encrypted under the key and bound to the entry path. The
+/// block table is reserved up front and filled in once every block is written.
+fn write_chunked<R: Read>(
+    out: &mut File,
+    source: &mut R,
+    size: u64,
+    block_size: u32,
+    seal: Option<(&[u8; 32], &str)>,
+) -> anyhow::Result<()> {
+    let mut table = BlockTable::new(block_size, size);
+    let table_pos = out.stream_position()?;
+    table.write_to(out)?;
+
+    let mut block = Vec::with_capacity(block_size as usize);
+    let mut total = 0u64;
+    for (index, len) in table.lengths.iter_mut().enumerate() {
+        block.clear();
+        source.by_ref().take(block_size as u64).read_to_end(&mut block)?;
+        let mut stored = zstd::bulk::compress(&block, 3)?;
+        if let Some((key, path)) = seal {
+            stored = crypto::encrypt(key, &stored, &crypto::entry_aad(path, Some(index)))?;
         }
+        out.write_all(&stored)?;
+        *len = stored.len() as u32;
+        total += block.len() as u64;
+    }
+    if total != size || source.read(&mut [0u8; 1])? != 0 {
+        anyhow::bail!("file changed size while it was being packed");
+    }
+
+    let end = out.stream_position()?;
+    out.seek(SeekFrom::Start(table_pos))?;
+    table.write_to(out)?;
+    out.seek(SeekFrom::Start(end))?;
+    Ok(())
+}
+
+/// Feeds everything read through SHA-256 and counts the bytes.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    len: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, hasher: Sha256::new(), len: 0 }
+    }
+
+    /// Returns the byte count and hex digest of everything read.
+    fn finish(self) -> (u64, String) {
+        (self.len, hex::encode(self.hasher.finalize()))
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.len += n as u64;
+        Ok(n)
     }
 }
@@ -0,0 +1,259 @@
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use std::collections::HashMap;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::aead::rand_core::RngCore;
+use argon2::{Algorithm, Argon2, Params, Version};
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use crate::error::{LayerPackError, Result};
+use crate::format::{Kdf, PackManifest};
+
+/// Supplies the decryption secret for a pack at load time, so different packs
+/// (or different releases) can use different keys.
+pub trait KeyProvider: Send + Sync {
+    /// Returns the secret for the pack described by `manifest`, or `None` if
+    /// this provider has no key for it.
+    fn key_for(&self, manifest: &PackManifest) -> Option<Vec<u8>>;
+}
+
+/// The same secret for every pack.
+pub struct StaticKey(pub Vec<u8>);
+
+impl StaticKey {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self(secret.into())
+    }
+}
+
+impl KeyProvider for StaticKey {
+    fn key_for(&self, _manifest: &PackManifest) -> Option<Vec<u8>> {
+        Some(self.0.clone())
+    }
+}
+
+/// Secrets looked up by the pack's `key_id`, so keys can rotate per release.
+/// Packs without a key ID get the `fallback` secret, if any.
+#[derive(Default)]
+pub struct KeyRing {
+    keys: HashMap<String, Vec<u8>>,
+    fallback: Option<Vec<u8>>,
+}
+
+impl KeyRing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key_id: impl Into<String>, secret: impl Into<Vec<u8>>) -> &mut Self {
+        self.keys.insert(key_id.into(), secret.into());
+        self
+    }
+
+    pub fn set_fallback(&mut self, secret: impl Into<Vec<u8>>) -> &mut Self {
+        self.fallback = Some(secret.into());
+        self
+    }
+}
+
+impl KeyProvider for KeyRing {
+    fn key_for(&self, manifest: &PackManifest) -> Option<Vec<u8>> {
+        match manifest.key_id() {
+            Some(id) => self.keys.get(id).cloned(),
+            None => self.fallback.clone(),
+        }
+    }
+}
+
+/// Key baked in at compile time from `LPACK_ENCRYPTION_KEY`.
+#[cfg(feature = "embedded-key")]
+pub struct EmbeddedKey;
+
+#[cfg(feature = "embedded-key")]
+impl KeyProvider for EmbeddedKey {
+    fn key_for(&self, _manifest: &PackManifest) -> Option<Vec<u8>> {
+        Some(env!("LPACK_ENCRYPTION_KEY").as_bytes().to_vec())
+    }
+}
+
+/// Provider used by the plain `LoadedPack::load*` constructors.
+pub(crate) fn default_key_provider() -> Option<&'static dyn KeyProvider> {
+    #[cfg(feature = "embedded-key")]
+    {
+        Some(&EmbeddedKey)
+    }
+    #[cfg(not(feature = "embedded-key"))]
+    {
+        None
+    }
+}
+
+/// AES-GCM nonce length. Encrypted entries are stored as `nonce || ciphertext`.
+pub const NONCE_LEN: usize = 12;
+
+const SALT_LEN: usize = 16;
+
+/// Upper bounds on KDF parameters read from a manifest. The manifest is only
+/// authenticated after the key is derived, so these keep a tampered pack from
+/// making the derivation allocate or spin without limit.
+pub const MAX_ARGON2_M_COST: u32 = 1024 * 1024;
+pub const MAX_ARGON2_T_COST: u32 = 10;
+pub const MAX_ARGON2_P_COST: u32 = 16;
+pub const MAX_HKDF_INFO_LEN: usize = 1024;
+const HKDF_INFO: &str = "layerpack entry key v1";
+const MAC_KEY_INFO: &[u8] = b"layerpack pack mac v1";
+const SIGNATURE_CONTEXT: &[u8] = b"layerpack pack signature v1";
+
+/// Turns a passphrase into an AES-256 key (`SHA-256(secret)`).
+/// This is the legacy derivation used by packs without `EncryptionInfo`.
+pub fn derive_key(secret: &[u8]) -> [u8; 32] {
+    Sha256::digest(secret).into()
+}
+
+/// Derives the AES-256 key for `secret` with the parameters in `kdf`.
+pub fn derive_key_with(kdf: &Kdf, secret: &[u8]) -> Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    match kdf {
+        Kdf::Sha256 => key = derive_key(secret),
+        Kdf::Hkdf { salt, info } => {
+            if info.len() > MAX_HKDF_INFO_LEN {
+                return Err(LayerPackError::InvalidKdf(format!("HKDF info is longer than {MAX_HKDF_INFO_LEN} bytes")));
+            }
+            let salt = decode_salt(salt)?;
+            Hkdf::<Sha256>::new(Some(&salt), secret)
+                .expand(info.as_bytes(), &mut key)
+                .map_err(|e| LayerPackError::InvalidKdf(e.to_string()))?;
+        }
+        Kdf::Argon2id { salt, m_cost, t_cost, p_cost } => {
+            if *m_cost > MAX_ARGON2_M_COST || *t_cost > MAX_ARGON2_T_COST || *p_cost > MAX_ARGON2_P_COST {
+                return Err(LayerPackError::InvalidKdf(format!(
+                    "Argon2id parameters m={m_cost} t={t_cost} p={p_cost} exceed the limits \
+                     m={MAX_ARGON2_M_COST} t={MAX_ARGON2_T_COST} p={MAX_ARGON2_P_COST}"
+                )));
+            }
+            let salt = decode_salt(salt)?;
+            let params = Params::new(*m_cost, *t_cost, *p_cost, Some(key.len()))
+                .map_err(|e| LayerPackError::InvalidKdf(e.to_string()))?;
+            Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                .hash_password_into(secret, &salt, &mut key)
+                .map_err(|e| LayerPackError::InvalidKdf(e.to_string()))?;
+        }
+    }
+    Ok(key)
+}
+
+/// HMAC-SHA256 tag over a pack's `auth_digest`. The MAC key is expanded from
+/// the entry key so the same secret never keys both AES-GCM and HMAC directly.
+pub fn pack_mac(key: &[u8; 32], digest: &[u8; 32]) -> [u8; 32] {
+    let mut mac = new_pack_mac(key);
+    mac.update(digest);
+    mac.finalize().into_bytes().into()
+}
+
+/// Constant-time check of a tag produced by `pack_mac`.
+pub fn verify_pack_mac(key: &[u8; 32], digest: &[u8; 32], tag: &[u8; 32]) -> bool {
+    let mut mac = new_pack_mac(key);
+    mac.update(digest);
+    mac.verify_slice(tag).is_ok()
+}
+
+fn new_pack_mac(key: &[u8; 32]) -> Hmac<Sha256> {
+    let mut mac_key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, key)
+        .expand(MAC_KEY_INFO, &mut mac_key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    <Hmac<Sha256> as Mac>::new_from_slice(&mac_key).expect("HMAC accepts any key length")
+}
+
+/// Ed25519 public key for a 32-byte signing key seed.
+pub fn signing_public_key(seed: &[u8; 32]) -> [u8; 32] {
+    SigningKey::from_bytes(seed).verifying_key().to_bytes()
+}
+
+/// Signs a pack's `auth_digest` with an Ed25519 seed. Returns the signature.
+pub fn sign_pack(seed: &[u8; 32], digest: &[u8; 32]) -> [u8; 64] {
+    SigningKey::from_bytes(seed)
+        .sign(&signature_message(digest))
+        .to_bytes()
+}
+
+/// Checks a signature produced by `sign_pack`.
+pub fn verify_pack_signature(public_key: &[u8; 32], digest: &[u8; 32], signature: &[u8; 64]) -> bool {
+    let Ok(key) = VerifyingKey::from_bytes(public_key) else {
+        return false;
+    };
+    let signature = ed25519_dalek::Signature::from_bytes(signature);
+    key.verify_strict(&signature_message(digest), &signature).is_ok()
+}
+
+/// Fresh random Ed25519 seed for `lpack keygen`.
+pub fn generate_signing_seed() -> [u8; 32] {
+    let mut seed = [0u8; 32];
+    OsRng.fill_bytes(&mut seed);
+    seed
+}
+
+fn signature_message(digest: &[u8; 32]) -> Vec<u8> {
+    [SIGNATURE_CONTEXT, &digest[..]].concat()
+}
+
+/// HKDF-SHA256 with a fresh random salt. Suitable for high-entropy secrets.
+pub fn new_hkdf() -> Kdf {
+    Kdf::Hkdf { salt: random_salt(), info: HKDF_INFO.to_string() }
+}
+
+/// Argon2id with a fresh random salt and the OWASP-recommended minimum cost
+/// (19 MiB, 2 passes, 1 lane). Use for human-chosen passphrases.
+pub fn new_argon2id() -> Kdf {
+    Kdf::Argon2id { salt: random_salt(), m_cost: 19 * 1024, t_cost: 2, p_cost: 1 }
+}
+
+fn random_salt() -> String {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    hex::encode(salt)
+}
+
+fn decode_salt(salt: &str) -> Result<Vec<u8>> {
+    hex::decode(salt).map_err(|_| LayerPackError::InvalidKdf("salt is not valid hex".to_string()))
+}
+
+/// Associated data binding a sealed payload to the entry at `path`. Blocks of
+/// chunked entries also bind their index, so they cannot be reordered.
+pub fn entry_aad(path: &str, block: Option<usize>) -> Vec<u8> {
+    let mut aad = path.as_bytes().to_vec();
+    if let Some(index) = block {
+        // Paths never contain NUL, so this cannot collide with another path
+        aad.push(0);
+        aad.extend_from_slice(&(index as u64).to_le_bytes());
+    }
+    aad
+}
+
+/// Encrypts `plaintext` under a fresh random nonce and prepends the nonce.
+/// `aad` is authenticated but not stored; `decrypt` must be given the same.
+pub fn encrypt(key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: plaintext, aad })
+        .map_err(|_| LayerPackError::Io(std::io::Error::other("AES-GCM encryption failed")))?;
+
+    let mut out = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+/// Reverses `encrypt`: splits off the nonce prefix and authenticates the rest.
+pub fn decrypt(key: &[u8; 32], data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < NONCE_LEN {
+        return Err(LayerPackError::DecryptFailed { key_id: None });
+    }
+    let (nonce_bytes, ciphertext) = data.split_at(NONCE_LEN);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    cipher
+        .decrypt(Nonce::from_slice(nonce_bytes), Payload { msg: ciphertext, aad })
+        .map_err(|_| LayerPackError::DecryptFailed { key_id: None })
+}
@@ -0,0 +1,98 @@
+use thiserror::Error;
+use crate::format::{FORMAT_VERSION, MIN_SUPPORTED_VERSION};
+
+pub type Result<T> = std::result::Result<T, LayerPackError>;
+
+/// Errors produced while opening packs and reading entries.
+///
+/// Bindings should not match on the message text; use `code()` (numeric, for C)
+/// or `code_name()` (string, for JS) instead. Both are stable across releases.
+#[derive(Debug, Error)]
+pub enum LayerPackError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid pack file format")]
+    BadMagic,
+    #[error("Unsupported pack format version {found} (supported: {min}..={max})")]
+    UnsupportedVersion { found: u32, min: u32, max: u32 },
+    #[error("Corrupt manifest: {0}")]
+    CorruptManifest(String),
+    #[error("Corrupt index: {0}")]
+    CorruptIndex(String),
+    #[error("File not found in pack: {0}")]
+    NotFound(String),
+    #[error("Decryption failed! Invalid key{} or corrupted data.", key_label(.key_id))]
+    DecryptFailed { key_id: Option<String> },
+    #[error("Hash mismatch for '{path}' (expected {expected}, got {actual})")]
+    HashMismatch { path: String, expected: String, actual: String },
+    #[error("Decompression error: {0}")]
+    Decompress(String),
+    #[error("Pack is encrypted but no decryption key{} was provided", key_label(.key_id))]
+    MissingKey { key_id: Option<String> },
+    #[error("Invalid key derivation parameters: {0}")]
+    InvalidKdf(String),
+    #[error("Pack authentication failed: the header, manifest or index was modified, or the key{} is wrong", key_label(.key_id))]
+    AuthFailed { key_id: Option<String> },
+    #[error("Pack '{pack}' rejected by trust policy: {reason}")]
+    Untrusted { pack: String, reason: String },
+    #[error("Invalid glob pattern: {0}")]
+    InvalidPattern(String),
+    #[error("Cannot apply merge patch to '{path}': {reason}")]
+    PatchFailed { path: String, reason: String },
+}
+
+fn key_label(key_id: &Option<String>) -> String {
+    match key_id {
+        Some(id) => format!(" (expected key '{}')", id),
+        None => String::new(),
+    }
+}
+
+impl LayerPackError {
+    pub fn unsupported_version(found: u32) -> Self {
+        Self::UnsupportedVersion { found, min: MIN_SUPPORTED_VERSION, max: FORMAT_VERSION }
+    }
+
+    /// Stable numeric code. Values start at 100 so the FFI layer can return
+    /// `-code()` without colliding with its own argument-validation codes.
+    pub fn code(&self) -> i32 {
+        match self {
+            Self::Io(_) => 100,
+            Self::BadMagic => 101,
+            Self::UnsupportedVersion { .. } => 102,
+            Self::CorruptManifest(_) => 103,
+            Self::CorruptIndex(_) => 104,
+            Self::NotFound(_) => 105,
+            Self::DecryptFailed { .. } => 106,
+            Self::HashMismatch { .. } => 107,
+            Self::Decompress(_) => 108,
+            Self::MissingKey { .. } => 109,
+            Self::InvalidKdf(_) => 110,
+            Self::AuthFailed { .. } => 111,
+            Self::Untrusted { .. } => 112,
+            Self::InvalidPattern(_) => 113,
+            Self::PatchFailed { .. } => 114,
+        }
+    }
+
+    /// Stable string code, used as the `code` property of JS errors.
+    pub fn code_name(&self) -> &'static str {
+        match self {
+            Self::Io(_) => "LPACK_IO",
+            Self::BadMagic => "LPACK_BAD_MAGIC",
+            Self::UnsupportedVersion { .. } => "LPACK_UNSUPPORTED_VERSION",
+            Self::CorruptManifest(_) => "LPACK_CORRUPT_MANIFEST",
+            Self::CorruptIndex(_) => "LPACK_CORRUPT_INDEX",
+            Self::NotFound(_) => "LPACK_NOT_FOUND",
+            Self::DecryptFailed { .. } => "LPACK_DECRYPT_FAILED",
+            Self::HashMismatch { .. } => "LPACK_HASH_MISMATCH",
+            Self::Decompress(_) => "LPACK_DECOMPRESS",
+            Self::MissingKey { .. } => "LPACK_MISSING_KEY",
+            Self::InvalidKdf(_) => "LPACK_INVALID_KDF",
+            Self::AuthFailed { .. } => "LPACK_AUTH_FAILED",
+            Self::Untrusted { .. } => "LPACK_UNTRUSTED",
+            Self::InvalidPattern(_) => "LPACK_INVALID_PATTERN",
+            Self::PatchFailed { .. } => "LPACK_PATCH_FAILED",
+        }
+    }
+}
@@ -1,16 +1,55 @@
 use std::ffi::CStr;
 use std::os::raw::{c_char, c_int};
 use std::path::Path;
+use crate::crypto::{self, KeyProvider, StaticKey};
+use crate::format::{DELETE_LIST, MERGE_PATCH_SUFFIX};
 use crate::resolver::LoadedPack;
 
 // ดึงรหัสผ่านมาจาก Environment ตอน build
 const SECURITY_KEY: &str = env!("LPACK_SECURITY_KEY");
 
+/// Returns 0 on success. Argument and output errors use small negative codes
+/// (-1..-10, -99); pack errors return `-LayerPackError::code()` (-100 and below).
+///
+/// Encrypted packs are only readable here when the library is built with the
+/// `embedded-key` feature; otherwise use `ffi_unpack_files_with_key`.
+///
+/// # Safety
+/// All three arguments must be null or valid NUL-terminated C strings.
 #[no_mangle]
 pub unsafe extern "C" fn ffi_unpack_files(
     key: *const c_char, // [SECURITY] รหัสลับต้องตรงกัน
     pack_path: *const c_char, 
     output_path: *const c_char
+) -> c_int {
+    unpack_files(key, pack_path, output_path, crypto::default_key_provider())
+}
+
+/// Same as `ffi_unpack_files`, but decrypts with `decrypt_key` (the pack
+/// secret, as given to `lpack create --encrypt-key`) instead of the built-in
+/// key source. A null `decrypt_key` behaves like `ffi_unpack_files`.
+///
+/// # Safety
+/// All four arguments must be null or valid NUL-terminated C strings.
+#[no_mangle]
+pub unsafe extern "C" fn ffi_unpack_files_with_key(
+    key: *const c_char,
+    pack_path: *const c_char,
+    output_path: *const c_char,
+    decrypt_key: *const c_char,
+) -> c_int {
+    if decrypt_key.is_null() {
+        return unpack_files(key, pack_path, output_path, crypto::default_key_provider());
+    }
+    let secret = StaticKey::new(CStr::from_ptr(decrypt_key).to_bytes());
+    unpack_files(key, pack_path, output_path, Some(&secret))
+}
+
+unsafe fn unpack_files(
+    key: *const c_char,
+    pack_path: *const c_char,
+    output_path: *const c_char,
+    keys: Option<&dyn KeyProvider>,
 ) -> c_int {
     if key.is_null() || pack_path.is_null() || output_path.is_null() {
         return -1;
@@ -39,13 +78,17 @@ pub unsafe extern "C" fn ffi_unpack_files(
     let pack_path = Path::new(pack_path_str);
     let output_path = Path::new(output_path_str);
 
-    match LoadedPack::load(pack_path) {
-        Ok(mut loaded) => {
-            let files = loaded.file_list();
-            for path in files {
-                match loaded.read_file(&path) {
+    match LoadedPack::load_with_key_provider(pack_path, keys) {
+        Ok(loaded) => {
+            for file in loaded.files() {
+                let path = &file.path;
+                match loaded.read_file(path) {
                     Ok(content) => {
-                         let out_file_path = output_path.join(&path);
+                         let out_file_path = if file.is_merge_patch() {
+                             output_path.join(format!("{path}{MERGE_PATCH_SUFFIX}"))
+                         } else {
+                             output_path.join(path)
+                         };
                          if let Some(parent) = out_file_path.parent() {
                              if std::fs::create_dir_all(parent).is_err() {
                                  return -3;
@@ -55,11 +98,20 @@ pub unsafe extern "C" fn ffi_unpack_files(
                              return -4;
                          }
                     },
-                    Err(_) => return -5,
+                    Err(e) => return -e.code(),
+                }
+            }
+            let deleted = loaded.deleted_paths();
+            if !deleted.is_empty() {
+                if std::fs::create_dir_all(output_path).is_err() {
+                    return -3;
+                }
+                if std::fs::write(output_path.join(DELETE_LIST), deleted.join("\n") + "\n").is_err() {
+                    return -4;
                 }
             }
             0
         },
-        Err(_) => -6,
+        Err(e) => -e.code(),
     }
 }
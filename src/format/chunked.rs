@@ -0,0 +1,296 @@
+//! Payload layout of `CompressionType::ZstdChunked` entries.
+//!
+//! ```text
+//! u32 block_size | u32 block_count | u32 compressed_len * block_count | blocks
+//! ```
+//!
+//! Every block holds `block_size` bytes of the original file (the last may be
+//! shorter) compressed as its own Zstd frame, so any byte range can be served
+//! by decoding only the blocks that cover it. All integers are little-endian.
+//!
+//! In encrypted entries each compressed block is sealed on its own as
+//! `nonce || ciphertext`, and the lengths in the table are those of the
+//! sealed blocks. Writers and readers then only ever hold one block.
+
+use std::borrow::Cow;
+use std::io::{Read, Write};
+use crate::error::{LayerPackError, Result};
+
+/// Block size `lpack create --chunk-size` uses when given no size.
+pub const DEFAULT_BLOCK_SIZE: u32 = 256 * 1024;
+
+/// Largest block a reader will allocate for.
+pub const MAX_BLOCK_SIZE: u32 = 64 * 1024 * 1024;
+
+/// Most decoded bytes a reader reserves up front per stored byte. Decoded
+/// sizes come from the index, so past this the buffer grows as data arrives.
+const PREALLOC_RATIO: u64 = 8;
+
+/// Capacity to reserve for `decoded_len` bytes decoded from `stored_len`.
+pub fn prealloc_len(decoded_len: u64, stored_len: u64) -> usize {
+    decoded_len.min(stored_len.saturating_mul(PREALLOC_RATIO)) as usize
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockTable {
+    pub block_size: u32,
+    /// Compressed length of each block.
+    pub lengths: Vec<u32>,
+    /// Start of each block, relative to the start of the payload.
+    offsets: Vec<u64>,
+}
+
+impl BlockTable {
+    /// Table for `original_size` bytes with every length still zero.
+    pub fn new(block_size: u32, original_size: u64) -> Self {
+        let count = original_size.div_ceil(block_size as u64) as usize;
+        Self::from_lengths(block_size, vec![0; count])
+    }
+
+    fn from_lengths(block_size: u32, lengths: Vec<u32>) -> Self {
+        let mut offsets = Vec::with_capacity(lengths.len());
+        let mut next = Self::encoded_len(lengths.len());
+        for len in &lengths {
+            offsets.push(next);
+            next += *len as u64;
+        }
+        Self { block_size, lengths, offsets }
+    }
+
+    /// Size of the table itself, i.e. where the first block starts.
+    pub fn encoded_len(count: usize) -> u64 {
+        8 + 4 * count as u64
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        out.write_all(&self.block_size.to_le_bytes())?;
+        out.write_all(&(self.lengths.len() as u32).to_le_bytes())?;
+        for len in &self.lengths {
+            out.write_all(&len.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Reads the table of an entry holding `original_size` bytes in
+    /// `payload_len` stored bytes, checking that the blocks fit exactly.
+    pub fn read_from<R: Read>(reader: &mut R, original_size: u64, payload_len: u64) -> Result<Self> {
+        let mut word = [0u8; 4];
+        reader.read_exact(&mut word)?;
+        let block_size = u32::from_le_bytes(word);
+        reader.read_exact(&mut word)?;
+        let count = u32::from_le_bytes(word) as usize;
+
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+            return Err(corrupt(format!("invalid block size {}", block_size)));
+        }
+        if count as u64 != original_size.div_ceil(block_size as u64) {
+            return Err(corrupt(format!("{} blocks cannot hold {} bytes", count, original_size)));
+        }
+        if Self::encoded_len(count) > payload_len {
+            return Err(corrupt("block table is longer than the entry".to_string()));
+        }
+
+        let mut table = vec![0u8; count * 4];
+        reader.read_exact(&mut table)?;
+        let lengths: Vec<u32> = table
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        let table = Self::from_lengths(block_size, lengths);
+        if table.end() != payload_len {
+            return Err(corrupt("block lengths do not match the entry size".to_string()));
+        }
+        Ok(table)
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.lengths.len()
+    }
+
+    /// Payload-relative offset and compressed length of block `index`.
+    pub fn block_span(&self, index: usize) -> (u64, usize) {
+        (self.offsets[index], self.lengths[index] as usize)
+    }
+
+    /// Block holding byte `pos` of the original file.
+    pub fn block_for(&self, pos: u64) -> usize {
+        (pos / self.block_size as u64) as usize
+    }
+
+    fn end(&self) -> u64 {
+        match (self.offsets.last(), self.lengths.last()) {
+            (Some(offset), Some(len)) => offset + *len as u64,
+            _ => Self::encoded_len(0),
+        }
+    }
+}
+
+/// Decompresses one block, which must expand to at most `block_size` bytes.
+pub fn decode_block(compressed: &[u8], block_size: u32) -> Result<Vec<u8>> {
+    zstd::bulk::decompress(compressed, block_size as usize)
+        .map_err(|e| LayerPackError::Decompress(format!("Zstd block: {}", e)))
+}
+
+/// Decodes a whole chunked payload held in memory. `open` turns stored block
+/// `index` into its Zstd frame; for encrypted entries it decrypts the block.
+pub fn decode_all<'a, F>(payload: &'a [u8], original_size: u64, open: F) -> Result<Vec<u8>>
+where
+    F: Fn(usize, &'a [u8]) -> Result<Cow<'a, [u8]>>,
+{
+    let table = BlockTable::read_from(&mut &payload[..], original_size, payload.len() as u64)?;
+    let mut data = Vec::with_capacity(prealloc_len(original_size, payload.len() as u64));
+    for index in 0..table.block_count() {
+        let (offset, len) = table.block_span(index);
+        let start = offset as usize;
+        let block = open(index, &payload[start..start + len])?;
+        data.extend_from_slice(&decode_block(&block, table.block_size)?);
+    }
+    if data.len() as u64 != original_size {
+        return Err(corrupt(format!("decoded {} bytes, index says {}", data.len(), original_size)));
+    }
+    Ok(data)
+}
+
+fn corrupt(msg: String) -> LayerPackError {
+    LayerPackError::Decompress(format!("Chunked entry: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto;
+
+    const BLOCK_SIZE: u32 = 4096;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    /// Chunked payload for `data`, with `seal` applied to each compressed block.
+    fn encode(data: &[u8], block_size: u32, seal: impl Fn(usize, Vec<u8>) -> Vec<u8>) -> Vec<u8> {
+        let mut table = BlockTable::new(block_size, data.len() as u64);
+        let mut blocks = Vec::new();
+        for (index, chunk) in data.chunks(block_size as usize).enumerate() {
+            let block = seal(index, zstd::bulk::compress(chunk, 3).unwrap());
+            table.lengths[index] = block.len() as u32;
+            blocks.extend_from_slice(&block);
+        }
+        let mut payload = Vec::new();
+        table.write_to(&mut payload).unwrap();
+        payload.extend_from_slice(&blocks);
+        payload
+    }
+
+    fn plain(_: usize, block: &[u8]) -> Result<Cow<'_, [u8]>> {
+        Ok(Cow::Borrowed(block))
+    }
+
+    fn read_table(payload: &[u8], original_size: u64) -> Result<BlockTable> {
+        BlockTable::read_from(&mut &payload[..], original_size, payload.len() as u64)
+    }
+
+    #[test]
+    fn round_trips_multi_block_payload() {
+        let data = sample(2 * BLOCK_SIZE as usize + 100);
+        let payload = encode(&data, BLOCK_SIZE, |_, block| block);
+
+        let table = read_table(&payload, data.len() as u64).unwrap();
+        assert_eq!(table.block_count(), 3);
+        assert_eq!(table.block_span(0).0, BlockTable::encoded_len(3));
+        assert_eq!(decode_all(&payload, data.len() as u64, plain).unwrap(), data);
+    }
+
+    #[test]
+    fn range_across_a_boundary_needs_only_its_blocks() {
+        let data = sample(3 * BLOCK_SIZE as usize);
+        let payload = encode(&data, BLOCK_SIZE, |_, block| block);
+        let table = read_table(&payload, data.len() as u64).unwrap();
+
+        let (start, end) = (BLOCK_SIZE as u64 - 10, BLOCK_SIZE as u64 + 10);
+        let (first, last) = (table.block_for(start), table.block_for(end - 1));
+        assert_eq!((first, last), (0, 1));
+
+        let mut decoded = Vec::new();
+        for index in first..=last {
+            let (offset, len) = table.block_span(index);
+            let raw = &payload[offset as usize..offset as usize + len];
+            decoded.extend_from_slice(&decode_block(raw, table.block_size).unwrap());
+        }
+        let within = start as usize - first * BLOCK_SIZE as usize;
+        assert_eq!(decoded[within..within + 20], data[start as usize..end as usize]);
+    }
+
+    #[test]
+    fn sealed_blocks_open_only_in_place() {
+        let key = [7u8; 32];
+        let data = sample(2 * BLOCK_SIZE as usize);
+        let seal = |index, block: Vec<u8>| {
+            crypto::encrypt(&key, &block, &crypto::entry_aad("movies/intro.webm", Some(index))).unwrap()
+        };
+        let open = |index, block: &[u8]| {
+            crypto::decrypt(&key, block, &crypto::entry_aad("movies/intro.webm", Some(index))).map(Cow::Owned)
+        };
+        let payload = encode(&data, BLOCK_SIZE, seal);
+        assert_eq!(decode_all(&payload, data.len() as u64, open).unwrap(), data);
+
+        // Equal-length blocks swapped in place still fail to open
+        let table = read_table(&payload, data.len() as u64).unwrap();
+        let ((a, a_len), (b, b_len)) = (table.block_span(0), table.block_span(1));
+        assert_eq!(a_len, b_len);
+        let mut swapped = payload.clone();
+        swapped[a as usize..a as usize + a_len].copy_from_slice(&payload[b as usize..b as usize + b_len]);
+        swapped[b as usize..b as usize + b_len].copy_from_slice(&payload[a as usize..a as usize + a_len]);
+        assert!(matches!(
+            decode_all(&swapped, data.len() as u64, open),
+            Err(LayerPackError::DecryptFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_block_size() {
+        let data = sample(100);
+        for block_size in [0, MAX_BLOCK_SIZE + 1] {
+            let mut payload = encode(&data, BLOCK_SIZE, |_, block| block);
+            payload[..4].copy_from_slice(&block_size.to_le_bytes());
+            assert!(matches!(read_table(&payload, 100), Err(LayerPackError::Decompress(_))), "block size {block_size}");
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_block_count() {
+        let data = sample(2 * BLOCK_SIZE as usize);
+        let payload = encode(&data, BLOCK_SIZE, |_, block| block);
+        for original_size in [BLOCK_SIZE as u64, 2 * BLOCK_SIZE as u64 + 1] {
+            assert!(matches!(read_table(&payload, original_size), Err(LayerPackError::Decompress(_))));
+        }
+
+        let mut huge_count = payload.clone();
+        huge_count[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        let original_size = u32::MAX as u64 * BLOCK_SIZE as u64;
+        assert!(matches!(read_table(&huge_count, original_size), Err(LayerPackError::Decompress(_))));
+    }
+
+    #[test]
+    fn rejects_lengths_that_do_not_fill_the_payload() {
+        let data = sample(2 * BLOCK_SIZE as usize);
+        let payload = encode(&data, BLOCK_SIZE, |_, block| block);
+
+        let mut short = payload.clone();
+        short.push(0);
+        let mut long_block = payload.clone();
+        let len = u32::from_le_bytes(payload[8..12].try_into().unwrap());
+        long_block[8..12].copy_from_slice(&(len + 1).to_le_bytes());
+
+        for payload in [&short, &long_block] {
+            assert!(matches!(read_table(payload, data.len() as u64), Err(LayerPackError::Decompress(_))));
+            assert!(decode_all(payload, data.len() as u64, plain).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_blocks_that_fall_short_of_the_entry() {
+        let data = sample(BLOCK_SIZE as usize + 1);
+        let payload = encode(&data, BLOCK_SIZE, |_, block| block);
+        assert!(matches!(decode_all(&payload, data.len() as u64 + 1, plain), Err(LayerPackError::Decompress(_))));
+    }
+}
@@ -0,0 +1,280 @@
+//! Binary file table used from format version 2 onwards.
+//!
+//! Layout (all integers little-endian):
+//!
+//! ```text
+//! u32 entry_count
+//! u32 string_table_len
+//! entry_count x record (RECORD_SIZE bytes each):
+//!     u32 path_offset      // into the string table
+//!     u32 path_len
+//!     u64 offset
+//!     u64 original_size
+//!     u64 compressed_size
+//!     u8  compression
+//!     u8  encryption
+//!     u8  flags            // bit 0: tombstone, bit 1: merge patch; others must be 0
+//!     u8  hash_len         // number of meaningful bytes in `hash`
+//!     [u8; 32] hash        // raw SHA-256 digest
+//! string_table (UTF-8 paths, no separators)
+//! ```
+
+use std::io::{Cursor, Read};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crate::error::{LayerPackError, Result};
+use super::{CompressionType, EncryptionType, EntryKind, FileEntry};
+
+const HASH_LEN: usize = 32;
+const FLAG_TOMBSTONE: u8 = 1;
+const FLAG_MERGE_PATCH: u8 = 2;
+const RECORD_SIZE: usize = 4 + 4 + 8 + 8 + 8 + 1 + 1 + 1 + 1 + HASH_LEN;
+
+pub fn encode_index(entries: &[FileEntry]) -> Result<Vec<u8>> {
+    let strings_len: usize = entries.iter().map(|e| e.path.len()).sum();
+    let mut out = Vec::with_capacity(8 + entries.len() * RECORD_SIZE + strings_len);
+
+    out.write_u32::<LittleEndian>(entries.len() as u32)?;
+    out.write_u32::<LittleEndian>(strings_len as u32)?;
+
+    let mut path_offset = 0u32;
+    for entry in entries {
+        let hash = hex::decode(&entry.hash)
+            .ok()
+            .filter(|h| h.len() <= HASH_LEN)
+            .ok_or_else(|| corrupt(format!("invalid hash for '{}'", entry.path)))?;
+
+        out.write_u32::<LittleEndian>(path_offset)?;
+        out.write_u32::<LittleEndian>(entry.path.len() as u32)?;
+        out.write_u64::<LittleEndian>(entry.offset)?;
+        out.write_u64::<LittleEndian>(entry.original_size)?;
+        out.write_u64::<LittleEndian>(entry.compressed_size)?;
+        out.write_u8(compression_code(entry.compression))?;
+        out.write_u8(encryption_code(entry.encryption))?;
+        out.write_u8(kind_flags(entry.kind))?;
+        out.write_u8(hash.len() as u8)?;
+        let mut hash_buf = [0u8; HASH_LEN];
+        hash_buf[..hash.len()].copy_from_slice(&hash);
+        out.extend_from_slice(&hash_buf);
+
+        path_offset += entry.path.len() as u32;
+    }
+
+    for entry in entries {
+        out.extend_from_slice(entry.path.as_bytes());
+    }
+
+    Ok(out)
+}
+
+pub fn decode_index(data: &[u8]) -> Result<Vec<FileEntry>> {
+    if data.len() < 8 {
+        return Err(corrupt("index is truncated"));
+    }
+    let mut cursor = Cursor::new(data);
+    let count = cursor.read_u32::<LittleEndian>()? as usize;
+    let strings_len = cursor.read_u32::<LittleEndian>()? as usize;
+
+    let records_end = count
+        .checked_mul(RECORD_SIZE)
+        .and_then(|n| n.checked_add(8))
+        .ok_or_else(|| corrupt("entry count overflows"))?;
+    if records_end.checked_add(strings_len) != Some(data.len()) {
+        return Err(corrupt("size does not match its header"));
+    }
+    let strings = &data[records_end..];
+
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        let path_offset = cursor.read_u32::<LittleEndian>()? as usize;
+        let path_len = cursor.read_u32::<LittleEndian>()? as usize;
+        let offset = cursor.read_u64::<LittleEndian>()?;
+        let original_size = cursor.read_u64::<LittleEndian>()?;
+        let compressed_size = cursor.read_u64::<LittleEndian>()?;
+        let compression = compression_from_code(cursor.read_u8()?)?;
+        let encryption = encryption_from_code(cursor.read_u8()?)?;
+        let kind = kind_from_flags(cursor.read_u8()?)?;
+        let hash_len = cursor.read_u8()? as usize;
+        let mut hash_buf = [0u8; HASH_LEN];
+        cursor.read_exact(&mut hash_buf)?;
+        if hash_len > HASH_LEN {
+            return Err(corrupt("invalid hash length"));
+        }
+
+        let path_bytes = path_offset
+            .checked_add(path_len)
+            .and_then(|end| strings.get(path_offset..end))
+            .ok_or_else(|| corrupt("path is outside the string table"))?;
+        let path = std::str::from_utf8(path_bytes)
+            .map_err(|_| corrupt("path is not valid UTF-8"))?
+            .to_string();
+
+        entries.push(FileEntry {
+            path,
+            offset,
+            original_size,
+            compressed_size,
+            compression,
+            encryption,
+            hash: hex::encode(&hash_buf[..hash_len]),
+            kind,
+        });
+    }
+
+    Ok(entries)
+}
+
+fn kind_flags(kind: EntryKind) -> u8 {
+    match kind {
+        EntryKind::File => 0,
+        EntryKind::Tombstone => FLAG_TOMBSTONE,
+        EntryKind::MergePatch => FLAG_MERGE_PATCH,
+    }
+}
+
+fn kind_from_flags(flags: u8) -> Result<EntryKind> {
+    // An unknown bit is a kind this runtime cannot serve correctly, not a hint
+    if flags & !(FLAG_TOMBSTONE | FLAG_MERGE_PATCH) != 0 {
+        return Err(corrupt(format!("unknown entry flags {:#04x}", flags)));
+    }
+    match (flags & FLAG_TOMBSTONE != 0, flags & FLAG_MERGE_PATCH != 0) {
+        (false, false) => Ok(EntryKind::File),
+        (true, false) => Ok(EntryKind::Tombstone),
+        (false, true) => Ok(EntryKind::MergePatch),
+        (true, true) => Err(corrupt("entry is both a tombstone and a merge patch")),
+    }
+}
+
+fn compression_code(compression: CompressionType) -> u8 {
+    match compression {
+        CompressionType::Store => 0,
+        CompressionType::Zstd => 1,
+        CompressionType::Lz4 => 2,
+        CompressionType::Lz4Frame => 3,
+        CompressionType::ZstdChunked => 4,
+    }
+}
+
+fn compression_from_code(code: u8) -> Result<CompressionType> {
+    match code {
+        0 => Ok(CompressionType::Store),
+        1 => Ok(CompressionType::Zstd),
+        2 => Ok(CompressionType::Lz4),
+        3 => Ok(CompressionType::Lz4Frame),
+        4 => Ok(CompressionType::ZstdChunked),
+        _ => Err(corrupt(format!("unknown compression type {}", code))),
+    }
+}
+
+fn encryption_code(encryption: EncryptionType) -> u8 {
+    match encryption {
+        EncryptionType::None => 0,
+        EncryptionType::Aes256Gcm => 1,
+    }
+}
+
+fn encryption_from_code(code: u8) -> Result<EncryptionType> {
+    match code {
+        0 => Ok(EncryptionType::None),
+        1 => Ok(EncryptionType::Aes256Gcm),
+        _ => Err(corrupt(format!("unknown encryption type {}", code))),
+    }
+}
+
+fn corrupt(reason: impl Into<String>) -> LayerPackError {
+    LayerPackError::CorruptIndex(reason.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, compression: CompressionType, encryption: EncryptionType, kind: EntryKind) -> FileEntry {
+        FileEntry {
+            path: path.to_string(),
+            offset: 13 + path.len() as u64,
+            original_size: 1 << 33,
+            compressed_size: 4096,
+            compression,
+            encryption,
+            hash: "ab".repeat(HASH_LEN),
+            kind,
+        }
+    }
+
+    fn assert_same(a: &[FileEntry], b: &[FileEntry]) {
+        assert_eq!(serde_json::to_value(a).unwrap(), serde_json::to_value(b).unwrap());
+    }
+
+    /// Byte offset of the flags of record `index`.
+    fn flags_at(index: usize) -> usize {
+        8 + index * RECORD_SIZE + 34
+    }
+
+    #[test]
+    fn round_trips_every_field() {
+        let mut short_hash = entry("data/Map001.json", CompressionType::Store, EncryptionType::None, EntryKind::File);
+        short_hash.hash = String::new();
+        let entries = vec![
+            entry("img/タイトル.png", CompressionType::Zstd, EncryptionType::Aes256Gcm, EntryKind::File),
+            entry("js/plugins.js", CompressionType::Lz4, EncryptionType::None, EntryKind::File),
+            entry("movies/intro.webm", CompressionType::ZstdChunked, EncryptionType::None, EntryKind::File),
+            entry("js/rpg_core.js", CompressionType::Lz4Frame, EncryptionType::None, EntryKind::File),
+            FileEntry::tombstone("audio/old.ogg".to_string()),
+            entry("data/Actors.json", CompressionType::Store, EncryptionType::None, EntryKind::MergePatch),
+            short_hash,
+        ];
+        let encoded = encode_index(&entries).unwrap();
+        assert_eq!(encoded.len(), 8 + entries.len() * RECORD_SIZE + entries.iter().map(|e| e.path.len()).sum::<usize>());
+        assert_same(&decode_index(&encoded).unwrap(), &entries);
+    }
+
+    #[test]
+    fn round_trips_empty_index() {
+        assert!(decode_index(&encode_index(&[]).unwrap()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn version_1_json_entries_survive_the_binary_table() {
+        // Version 1 indexes predate `encryption` and `kind`
+        let json = r#"[
+            {"path":"data/Map001.json","offset":167,"original_size":48,"compressed_size":48,
+             "compression":"store","hash":"a1b2c3"},
+            {"path":"data/System.json","offset":215,"original_size":11228,"compressed_size":954,
+             "compression":"zstd","hash":""}
+        ]"#;
+        let entries: Vec<FileEntry> = serde_json::from_str(json).unwrap();
+        assert_eq!(entries[0].encryption, EncryptionType::None);
+        assert_eq!(entries[0].kind, EntryKind::File);
+
+        let decoded = decode_index(&encode_index(&entries).unwrap()).unwrap();
+        assert_same(&decoded, &entries);
+    }
+
+    #[test]
+    fn rejects_unknown_flags() {
+        let entries = [entry("a.txt", CompressionType::Store, EncryptionType::None, EntryKind::File)];
+        for flags in [4, 0x80, FLAG_TOMBSTONE | 8, FLAG_TOMBSTONE | FLAG_MERGE_PATCH] {
+            let mut encoded = encode_index(&entries).unwrap();
+            encoded[flags_at(0)] = flags;
+            assert!(matches!(decode_index(&encoded), Err(LayerPackError::CorruptIndex(_))), "flags {flags:#04x}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_tables() {
+        let entries = [entry("a.txt", CompressionType::Store, EncryptionType::None, EntryKind::File)];
+        let encoded = encode_index(&entries).unwrap();
+
+        let truncated = &encoded[..encoded.len() - 1];
+        let mut huge_count = encoded.clone();
+        huge_count[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut bad_path = encoded.clone();
+        bad_path[8..12].copy_from_slice(&1u32.to_le_bytes());
+        let mut bad_compression = encoded.clone();
+        bad_compression[flags_at(0) - 2] = 9;
+
+        for data in [&encoded[..4], truncated, &huge_count, &bad_path, &bad_compression] {
+            assert!(matches!(decode_index(data), Err(LayerPackError::CorruptIndex(_))));
+        }
+    }
+}
@@ -1,7 +1,38 @@
 use serde::{Deserialize, Serialize};
 
+pub mod chunked;
+pub mod index;
+pub mod path;
+pub mod trailer;
+
 pub const CONTENT_TYPE: &str = "application/vnd.layerpack";
 
+pub const MAGIC: &[u8; 5] = b"LPACK";
+
+/// Version 1 packs store the file table as a JSON array of `FileEntry`.
+pub const FORMAT_VERSION_JSON_INDEX: u32 = 1;
+/// Version 2 packs store the file table in the binary layout from `index`.
+pub const FORMAT_VERSION_BINARY_INDEX: u32 = 2;
+/// Version 3 packs add the record trailer from `trailer` after the binary index.
+pub const FORMAT_VERSION_TRAILER: u32 = 3;
+/// Version 4 packs bind each encrypted entry to its path: the path (and block
+/// index) is AES-GCM associated data, so ciphertexts cannot be swapped.
+pub const FORMAT_VERSION_ENTRY_AAD: u32 = 4;
+/// Version written by `PackBuilder`.
+pub const FORMAT_VERSION: u32 = FORMAT_VERSION_ENTRY_AAD;
+/// Oldest version this runtime can still read.
+pub const MIN_SUPPORTED_VERSION: u32 = FORMAT_VERSION_JSON_INDEX;
+
+/// Optional file in the source directory listing paths, one per line, that
+/// the pack deletes from lower layers. Blank lines and `#` comments are skipped.
+/// Unpacking writes a pack's tombstones back out to it.
+pub const DELETE_LIST: &str = ".lpack-delete";
+
+/// Files named `<path>.lpack-merge` are stored as JSON Merge Patch (RFC 7396)
+/// entries for `<path>`, applied to the lower layers' file at resolve time.
+/// Unpacking writes merge-patch entries back under the same name.
+pub const MERGE_PATCH_SUFFIX: &str = ".lpack-merge";
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum PackType {
@@ -19,7 +50,14 @@ pub enum PackType {
 pub enum CompressionType {
     Store,
     Zstd,
+    /// LZ4 block with the original size prepended (`lz4_flex::compress_prepend_size`).
     Lz4,
+    /// LZ4 frame format, written by the streaming builder.
+    Lz4Frame,
+    /// Independently compressed Zstd blocks behind a block table (see `chunked`),
+    /// so a byte range can be read without decoding the whole entry.
+    #[serde(rename = "zstd-chunked")]
+    ZstdChunked,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -38,19 +76,61 @@ pub struct PackManifest {
     pub author: Option<String>,
     #[serde(default)]
     pub website: Option<String>,
+    /// Present on packs whose entries are encrypted by a current builder.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionInfo>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+impl PackManifest {
+    /// ID of the key this pack was encrypted with, if the builder recorded one.
+    pub fn key_id(&self) -> Option<&str> {
+        self.encryption.as_ref()?.key_id.as_deref()
+    }
+}
+
+/// How to turn the caller's secret into the pack's AES-256 key.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EncryptionInfo {
+    /// Free-form label (e.g. "release-2026-10") so a key provider can pick
+    /// the right secret and errors can say which key was expected.
+    #[serde(default)]
+    pub key_id: Option<String>,
+    pub kdf: Kdf,
+}
+
+/// Key derivation function. Salts are hex-encoded.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "alg", rename_all = "lowercase")]
+pub enum Kdf {
+    /// `SHA-256(secret)` with no salt. Used by packs without `EncryptionInfo`.
+    Sha256,
+    /// HKDF-SHA256 (RFC 5869).
+    Hkdf { salt: String, info: String },
+    /// Argon2id, for low-entropy secrets such as passphrases. `m_cost` is in KiB;
+    /// readers refuse costs above the `crypto::MAX_ARGON2_*` limits.
+    Argon2id { salt: String, m_cost: u32, t_cost: u32, p_cost: u32 },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum EncryptionType {
+    #[default]
     None,
     Aes256Gcm,
 }
 
-impl Default for EncryptionType {
-    fn default() -> Self {
-        Self::None
-    }
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    #[default]
+    File,
+    /// Deletes the path from lower layers: a `Resolver` that reaches this
+    /// entry reports the file as not found. Has no data.
+    Tombstone,
+    /// A JSON Merge Patch (RFC 7396) document. A `Resolver` applies it to
+    /// the file from the layers below instead of replacing that file.
+    #[serde(rename = "merge-patch")]
+    MergePatch,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -63,4 +143,36 @@ pub struct FileEntry {
     #[serde(default)]
     pub encryption: EncryptionType,
     pub hash: String, 
+    #[serde(default)]
+    pub kind: EntryKind,
+}
+
+impl FileEntry {
+    /// An entry hiding `path` in lower layers.
+    pub fn tombstone(path: String) -> Self {
+        Self {
+            path,
+            offset: 0,
+            original_size: 0,
+            compressed_size: 0,
+            compression: CompressionType::Store,
+            encryption: EncryptionType::None,
+            hash: String::new(),
+            kind: EntryKind::Tombstone,
+        }
+    }
+
+    pub fn is_tombstone(&self) -> bool {
+        self.kind == EntryKind::Tombstone
+    }
+
+    /// Encrypted `ZstdChunked` entries seal each block on its own instead of
+    /// the whole payload (see `chunked`).
+    pub fn seals_blocks(&self) -> bool {
+        self.encryption != EncryptionType::None && self.compression == CompressionType::ZstdChunked
+    }
+
+    pub fn is_merge_patch(&self) -> bool {
+        self.kind == EntryKind::MergePatch
+    }
 }
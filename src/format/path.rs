@@ -0,0 +1,79 @@
+//! Canonical form of paths inside a pack, shared by the builder and readers.
+//!
+//! Stored paths are relative, use `/` and contain no empty, `.` or `..`
+//! segments. Lookups go through the same function, so `./img\Title.png`
+//! and `/img/Title.png` both find `img/Title.png` on any platform.
+
+use std::borrow::Cow;
+
+/// Canonicalizes separators and dot segments. `..` never climbs above the
+/// pack root. Already-canonical paths are returned without allocating.
+pub fn normalize(path: &str) -> Cow<'_, str> {
+    let canonical = !path.contains('\\')
+        && path.split('/').all(|segment| !matches!(segment, "" | "." | ".."));
+    if canonical {
+        return Cow::Borrowed(path);
+    }
+
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split(['/', '\\']) {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    Cow::Owned(segments.join("/"))
+}
+
+/// Key for case-insensitive lookups: the normalized path, lowercased.
+pub fn fold_case(path: &str) -> String {
+    normalize(path).to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_paths_are_borrowed() {
+        for path in ["img/Title.png", "data/Map001.json", "a"] {
+            assert!(matches!(normalize(path), Cow::Borrowed(p) if p == path), "{path}");
+        }
+    }
+
+    #[test]
+    fn dot_dot_never_climbs_above_the_root() {
+        let cases = [
+            ("../a.txt", "a.txt"),
+            ("../../img/a.png", "img/a.png"),
+            ("img/../../a.png", "a.png"),
+            ("img/sub/../../..", ""),
+            ("..", ""),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(normalize(path), expected, "{path}");
+        }
+    }
+
+    #[test]
+    fn mixed_separators_and_dot_segments() {
+        let cases = [
+            ("img\\Title.png", "img/Title.png"),
+            ("./img\\sub/../Title.png", "img/Title.png"),
+            ("/img//Title.png", "img/Title.png"),
+            ("\\\\img\\.\\Title.png\\", "img/Title.png"),
+            ("data\\..\\js/./plugins.js", "js/plugins.js"),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(normalize(path), expected, "{path}");
+        }
+    }
+
+    #[test]
+    fn fold_case_normalizes_first() {
+        assert_eq!(fold_case("./IMG\\Title.PNG"), "img/title.png");
+    }
+}
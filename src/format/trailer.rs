@@ -0,0 +1,134 @@
+//! Trailer records written after the index from format version 3 onwards.
+//!
+//! The trailer runs from the end of the index to the end of the pack and is a
+//! sequence of `[u8 kind][u32 len][len bytes]` records. Readers skip kinds they
+//! do not know, so new records can be added without another version bump.
+//!
+//! Record kinds:
+//! - `1` auth: `[u8 alg][32-byte tag]` over `auth_digest`
+//!   (alg `1` = plain SHA-256, alg `2` = HMAC-SHA256 under the pack key).
+//! - `2` signature: `[32-byte Ed25519 public key][64-byte signature]` over
+//!   `auth_digest` (see `crypto::sign_pack`).
+
+use std::io::{Cursor, Read};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use sha2::{Digest, Sha256};
+use crate::error::{LayerPackError, Result};
+use super::MAGIC;
+
+const RECORD_AUTH: u8 = 1;
+const RECORD_SIGNATURE: u8 = 2;
+
+const AUTH_SHA256: u8 = 1;
+const AUTH_HMAC_SHA256: u8 = 2;
+
+/// Largest trailer a reader will accept.
+pub const MAX_TRAILER_LEN: u64 = 64 * 1024;
+
+/// Pack-level tag over the header, manifest and index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthTag {
+    /// Unkeyed digest. Catches corruption, but anyone can recompute it, so
+    /// it gives unencrypted packs no tamper protection; only a publisher
+    /// signature does.
+    Sha256([u8; 32]),
+    /// Keyed MAC; only holders of the pack key can produce it.
+    HmacSha256([u8; 32]),
+}
+
+/// Ed25519 signature by the pack's publisher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackSignature {
+    pub public_key: [u8; 32],
+    pub signature: [u8; 64],
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Trailer {
+    pub auth: Option<AuthTag>,
+    pub signature: Option<PackSignature>,
+}
+
+/// SHA-256 over every byte the auth tag protects: magic, version, manifest
+/// length and bytes, index pointers, and the index itself.
+pub fn auth_digest(version: u32, manifest: &[u8], index_offset: u64, index: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(MAGIC);
+    hasher.update(version.to_le_bytes());
+    hasher.update((manifest.len() as u32).to_le_bytes());
+    hasher.update(manifest);
+    hasher.update(index_offset.to_le_bytes());
+    hasher.update((index.len() as u32).to_le_bytes());
+    hasher.update(index);
+    hasher.finalize().into()
+}
+
+pub fn encode_trailer(trailer: &Trailer) -> Vec<u8> {
+    let mut out = Vec::new();
+    if let Some(auth) = trailer.auth {
+        let (alg, tag) = match auth {
+            AuthTag::Sha256(tag) => (AUTH_SHA256, tag),
+            AuthTag::HmacSha256(tag) => (AUTH_HMAC_SHA256, tag),
+        };
+        write_record(&mut out, RECORD_AUTH, &[&[alg][..], &tag[..]].concat());
+    }
+    if let Some(sig) = trailer.signature {
+        write_record(&mut out, RECORD_SIGNATURE, &[&sig.public_key[..], &sig.signature[..]].concat());
+    }
+    out
+}
+
+pub fn decode_trailer(data: &[u8]) -> Result<Trailer> {
+    let mut trailer = Trailer::default();
+    let mut cursor = Cursor::new(data);
+    while (cursor.position() as usize) < data.len() {
+        let kind = cursor.read_u8().map_err(|_| corrupt("truncated record"))?;
+        let len = cursor.read_u32::<LittleEndian>().map_err(|_| corrupt("truncated record"))? as usize;
+        let start = cursor.position() as usize;
+        let payload = start
+            .checked_add(len)
+            .and_then(|end| data.get(start..end))
+            .ok_or_else(|| corrupt("record runs past the end of the pack"))?;
+        cursor.set_position((start + len) as u64);
+
+        match kind {
+            RECORD_AUTH => trailer.auth = Some(decode_auth(payload)?),
+            RECORD_SIGNATURE => trailer.signature = Some(decode_signature(payload)?),
+            _ => {}
+        }
+    }
+    Ok(trailer)
+}
+
+fn decode_auth(payload: &[u8]) -> Result<AuthTag> {
+    let mut cursor = Cursor::new(payload);
+    let alg = cursor.read_u8().map_err(|_| corrupt("empty auth record"))?;
+    let mut tag = [0u8; 32];
+    cursor.read_exact(&mut tag).map_err(|_| corrupt("short auth tag"))?;
+    match alg {
+        AUTH_SHA256 => Ok(AuthTag::Sha256(tag)),
+        AUTH_HMAC_SHA256 => Ok(AuthTag::HmacSha256(tag)),
+        other => Err(corrupt(format!("unknown auth algorithm {}", other))),
+    }
+}
+
+fn decode_signature(payload: &[u8]) -> Result<PackSignature> {
+    if payload.len() != 32 + 64 {
+        return Err(corrupt("signature record has the wrong size"));
+    }
+    let mut sig = PackSignature { public_key: [0u8; 32], signature: [0u8; 64] };
+    sig.public_key.copy_from_slice(&payload[..32]);
+    sig.signature.copy_from_slice(&payload[32..]);
+    Ok(sig)
+}
+
+fn write_record(out: &mut Vec<u8>, kind: u8, payload: &[u8]) {
+    out.push(kind);
+    // Writing into a Vec cannot fail
+    let _ = out.write_u32::<LittleEndian>(payload.len() as u32);
+    out.extend_from_slice(payload);
+}
+
+fn corrupt(reason: impl Into<String>) -> LayerPackError {
+    LayerPackError::CorruptIndex(format!("trailer: {}", reason.into()))
+}
@@ -1,3 +1,5 @@
+pub mod crypto;
+pub mod error;
 pub mod format;
 pub mod resolver;
 pub mod ffi;
@@ -5,3 +7,5 @@ pub mod ffi;
 // จะ Compile ส่วนนี้ก็ต่อเมื่อสั่งเปิด feature "builder" เท่านั้น
 #[cfg(feature = "builder")]
 pub mod builder;
+
+pub use error::{LayerPackError, Result};
@@ -1,11 +1,13 @@
 use clap::{Parser, Subcommand, ValueEnum};
-use std::path::PathBuf;
-use layer_pack::format::PackType;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use layer_pack::format::{Kdf, PackType, DELETE_LIST, MERGE_PATCH_SUFFIX};
 #[cfg(feature = "builder")]
-use layer_pack::format::PackManifest;
+use layer_pack::format::{chunked, PackManifest};
 #[cfg(feature = "builder")]
-use layer_pack::builder::PackBuilder;
-use layer_pack::resolver::{Resolver, LoadedPack};
+use layer_pack::builder::{PackBuilder, EncryptionOptions};
+use layer_pack::resolver::{Resolver, LoadedPack, VerifyPolicy};
+use layer_pack::crypto::StaticKey;
 
 #[derive(Parser)]
 #[command(name = "lpack")]
@@ -13,8 +15,13 @@ use layer_pack::resolver::{Resolver, LoadedPack};
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Key for encrypted packs (falls back to the LPACK_ENCRYPTION_KEY environment variable)
+    #[arg(long, global = true)]
+    decrypt_key: Option<String>,
 }
 
+// Parsed once at startup; boxing `Create`'s options would buy nothing
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
     /// Create a new pack from a directory
@@ -47,6 +54,32 @@ enum Commands {
         /// Website URL
         #[arg(long)]
         website: Option<String>,
+        /// Encrypt entries with AES-256-GCM using this key
+        #[arg(long)]
+        encrypt_key: Option<String>,
+        /// Only encrypt paths matching this glob (repeatable; default: all files)
+        #[arg(long, requires = "encrypt_key")]
+        encrypt: Vec<String>,
+        /// Key ID recorded in the manifest so readers can pick the right key
+        #[arg(long, requires = "encrypt_key")]
+        key_id: Option<String>,
+        /// Key derivation function for the encryption key
+        #[arg(long, value_enum, default_value_t = KdfArg::Hkdf)]
+        kdf: KdfArg,
+        /// Sign the pack with the Ed25519 key in this file (hex seed, see `keygen`)
+        #[arg(long)]
+        sign_key: Option<PathBuf>,
+        /// Split Zstd entries larger than this many KiB into independently
+        /// compressed blocks, so ranges can be read without decoding the whole file
+        /// (256 KiB if no size is given)
+        #[arg(long, value_name = "KIB", num_args = 0..=1)]
+        chunk_size: Option<Option<u32>>,
+    },
+    /// Generate an Ed25519 signing key for `create --sign-key`
+    #[cfg(feature = "builder")]
+    Keygen {
+        /// Where to write the private key (hex seed)
+        output: PathBuf,
     },
     /// List files in a pack
     List {
@@ -55,24 +88,49 @@ enum Commands {
     },
     /// Resolve a file path across multiple packs
     Resolve {
-        /// Directory containing packs
+        /// Directory containing packs, loaded in file name order
         #[arg(short, long)]
         dir: Option<PathBuf>,
         /// Specific pack files
         #[arg(short, long)]
         packs: Vec<PathBuf>,
+        /// Only use packs for this locale (e.g., "th-TH", falling back to th, then en)
+        #[arg(long)]
+        locale: Option<String>,
+        /// Match paths regardless of case (e.g., Title.PNG finds title.png)
+        #[arg(long)]
+        ignore_case: bool,
         /// Virtual path to resolve
         path: String,
     },
+    /// Check every file in a pack against its stored hash and layout
+    Verify {
+        /// Pack file
+        pack: PathBuf,
+        /// Require a valid signature from this publisher key (hex; repeatable)
+        #[arg(long)]
+        trust: Vec<String>,
+    },
     /// Unpack files from a pack
     Unpack {
         /// Pack file
         pack: PathBuf,
         /// Output directory
         output: PathBuf,
+        /// Check every file against its stored SHA-256 hash
+        #[arg(long)]
+        verify: bool,
     },
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum KdfArg {
+    /// HKDF-SHA256, for random high-entropy keys
+    Hkdf,
+    /// Argon2id, for human-chosen passphrases
+    Argon2,
+}
+
 #[derive(Clone, ValueEnum)]
 enum PackTypeArg {
     Base,
@@ -96,12 +154,27 @@ impl From<PackTypeArg> for PackType {
     }
 }
 
+fn parse_key_hex(text: &str) -> anyhow::Result<[u8; 32]> {
+    let bytes = hex::decode(text.trim())?;
+    bytes.try_into().map_err(|_| anyhow::anyhow!("Key must be 32 bytes (64 hex characters)"))
+}
+
+fn open_pack(path: &Path, keys: Option<&StaticKey>) -> layer_pack::Result<LoadedPack> {
+    match keys {
+        Some(keys) => LoadedPack::load_with_key_provider(path, Some(keys)),
+        None => LoadedPack::load(path),
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+    let keys = cli.decrypt_key
+        .or_else(|| std::env::var("LPACK_ENCRYPTION_KEY").ok())
+        .map(StaticKey::new);
 
     match cli.command {
         #[cfg(feature = "builder")]
-        Commands::Create { source, output, name, type_, lang, priority, r#ref, author, website } => {
+        Commands::Create { source, output, name, type_, lang, priority, r#ref, author, website, encrypt_key, encrypt, key_id, kdf, sign_key, chunk_size } => {
             let manifest = PackManifest {
                 name,
                 pack_type: type_.into(),
@@ -112,6 +185,7 @@ fn main() -> anyhow::Result<()> {
                 custom_ref: r#ref,
                 author,
                 website,
+                encryption: None,
             };
             let mut output_path = output;
             if let Some(ext) = output_path.extension() {
@@ -122,13 +196,35 @@ fn main() -> anyhow::Result<()> {
                 output_path.set_extension("lpack");
             }
 
-            let builder = PackBuilder::new(manifest);
+            let mut builder = PackBuilder::new(manifest);
+            if let Some(key) = encrypt_key {
+                let kdf = match kdf {
+                    KdfArg::Hkdf => layer_pack::crypto::new_hkdf(),
+                    KdfArg::Argon2 => layer_pack::crypto::new_argon2id(),
+                };
+                builder = builder.with_encryption(key.as_bytes(), &encrypt, EncryptionOptions { key_id, kdf })?;
+            }
+            if let Some(key_file) = sign_key {
+                let seed = parse_key_hex(&std::fs::read_to_string(key_file)?)?;
+                builder = builder.with_signing_key(seed);
+            }
+            if let Some(kib) = chunk_size {
+                let block_size = kib.map_or(chunked::DEFAULT_BLOCK_SIZE, |kib| kib.saturating_mul(1024));
+                builder = builder.with_chunking(block_size)?;
+            }
             builder.build(source, output_path)?;
             println!("Pack created successfully.");
         }
+        #[cfg(feature = "builder")]
+        Commands::Keygen { output } => {
+            let seed = layer_pack::crypto::generate_signing_seed();
+            std::fs::write(&output, hex::encode(seed))?;
+            println!("Private key written to {}", output.display());
+            println!("Public key: {}", hex::encode(layer_pack::crypto::signing_public_key(&seed)));
+        }
 
         Commands::List { pack } => {
-            let loaded = LoadedPack::load(pack)?;
+            let loaded = open_pack(&pack, keys.as_ref())?;
             println!("Pack: {} (Type: {:?}, Lang: {:?}, Priority: {})", 
                 loaded.manifest.name, 
                 loaded.manifest.pack_type, 
@@ -136,6 +232,11 @@ fn main() -> anyhow::Result<()> {
                 loaded.manifest.priority
             );
             println!("MIME Type: {}", layer_pack::format::CONTENT_TYPE);
+            println!("Format Version: {}", loaded.format_version());
+            println!("Authentication: {:?}", loaded.auth_status());
+            if let Some(sig) = loaded.signature() {
+                println!("Signed by: {} ({})", hex::encode(sig.signer), if sig.valid { "valid" } else { "INVALID" });
+            }
             if let Some(r) = &loaded.manifest.custom_ref {
                 println!("Ref: {}", r);
             }
@@ -145,47 +246,78 @@ fn main() -> anyhow::Result<()> {
             if let Some(w) = &loaded.manifest.website {
                 println!("Website: {}", w);
             }
-            println!("{:<50} | {:<10} | {:<10} | {:<10}", "Path", "Size", "CmpSize", "Method");
-            println!("{:-<90}", "");
-            
-            let mut files = loaded.file_list();
-            files.sort();
+            if let Some(info) = &loaded.manifest.encryption {
+                let kdf = match &info.kdf {
+                    Kdf::Sha256 => "sha256",
+                    Kdf::Hkdf { .. } => "hkdf-sha256",
+                    Kdf::Argon2id { .. } => "argon2id",
+                };
+                println!("Encryption: key {} (KDF: {})", info.key_id.as_deref().unwrap_or("<unnamed>"), kdf);
+            }
+            println!("{:<50} | {:<10} | {:<10} | {:<6} | {:<10}", "Path", "Size", "CmpSize", "Method", "Encryption");
+            println!("{:-<100}", "");
             
-            for path in files {
+            for path in loaded.file_list() {
                 if let Some(entry) = loaded.get_entry(&path) {
-                     println!("{:<50} | {:<10} | {:<10} | {:?}", 
+                     println!("{:<50} | {:<10} | {:<10} | {:<6} | {:?}{}", 
                         path, 
                         entry.original_size, 
                         entry.compressed_size, 
-                        entry.compression
+                        format!("{:?}", entry.compression),
+                        entry.encryption,
+                        if entry.is_merge_patch() { " (merge patch)" } else { "" }
                     );
                 }
             }
+
+            for path in loaded.deleted_paths() {
+                println!("{:<50} | (deleted from lower layers)", path);
+            }
         }
-        Commands::Resolve { dir, packs, path } => {
-            let mut resolver = Resolver::new();
+        Commands::Resolve { dir, packs, locale, ignore_case, path } => {
+            let mut resolver = match keys {
+                Some(keys) => Resolver::with_key_provider(Arc::new(keys)),
+                None => Resolver::new(),
+            };
+            if let Some(locale) = &locale {
+                resolver.set_locale(locale);
+            }
+            resolver.set_case_insensitive(ignore_case);
             
             if let Some(d) = dir {
                 if d.exists() && d.is_dir() {
+                    // read_dir order differs between machines; sort so
+                    // equal-priority packs always tie-break the same way
+                    let mut found = Vec::new();
                     for entry in std::fs::read_dir(d)? {
-                        let entry = entry?;
-                        let p = entry.path();
-                        if p.extension().map_or(false, |e| e == "pack") {
-                             match LoadedPack::load(&p) {
-                                Ok(pack) => resolver.add_pack(pack),
-                                Err(e) => eprintln!("Failed to load {:?}: {}", p, e),
-                             }
+                        let p = entry?.path();
+                        if p.extension().is_some_and(|e| e == "pack") {
+                            found.push(p);
                         }
                     }
+                    found.sort();
+                    for p in found {
+                         if let Err(e) = resolver.load_pack(&p) {
+                            eprintln!("Failed to load {:?}: {}", p, e);
+                         }
+                    }
                 }
             }
             for p in packs {
-                 match LoadedPack::load(&p) {
-                    Ok(pack) => resolver.add_pack(pack),
-                    Err(e) => eprintln!("Failed to load {:?}: {}", p, e),
+                 if let Err(e) = resolver.load_pack(&p) {
+                    eprintln!("Failed to load {:?}: {}", p, e);
                  }
             }
 
+            for conflict in resolver.priority_conflicts().iter().filter(|c| c.path == path) {
+                let names: Vec<&str> = conflict.packs.iter()
+                    .filter_map(|h| resolver.pack(*h))
+                    .map(|p| p.manifest.name.as_str())
+                    .collect();
+                eprintln!("Warning: {} share priority {} for '{}'; '{}' wins because it was loaded first",
+                    names.join(", "), conflict.priority, path, names[0]);
+            }
+
             let layers = resolver.list_layers(&path);
             if layers.is_empty() {
                 println!("File '{}' not found in any pack.", path);
@@ -195,7 +327,7 @@ fn main() -> anyhow::Result<()> {
                     println!(" - {}", layer);
                 }
                 
-                if let Some(data) = resolver.resolve(&path) {
+                if let Some(data) = resolver.try_resolve(&path)? {
                     println!("Resolved content size: {} bytes", data.len());
                     // Try to print as string if it looks like text
                     if let Ok(text) = String::from_utf8(data) {
@@ -208,21 +340,79 @@ fn main() -> anyhow::Result<()> {
                 }
             }
         }
-        Commands::Unpack { pack, output } => {
-            let mut loaded = LoadedPack::load(&pack)?;
+        Commands::Verify { pack, trust } => {
+            let loaded = open_pack(&pack, keys.as_ref())?;
+            println!("Verifying {} ({} files)...", pack.display(), loaded.file_list().len());
+
+            let trusted = trust.iter().map(|t| parse_key_hex(t)).collect::<anyhow::Result<Vec<_>>>()?;
+            let signature_ok = match loaded.signature() {
+                Some(sig) if !sig.valid => {
+                    println!("FAIL  signature by {} is invalid", hex::encode(sig.signer));
+                    false
+                }
+                Some(sig) if !trusted.is_empty() && !trusted.contains(&sig.signer) => {
+                    println!("FAIL  signer {} is not trusted", hex::encode(sig.signer));
+                    false
+                }
+                Some(sig) => {
+                    println!("OK    signed by {}", hex::encode(sig.signer));
+                    true
+                }
+                None if !trusted.is_empty() => {
+                    println!("FAIL  pack is not signed");
+                    false
+                }
+                None => true,
+            };
+
+            let report = loaded.check_integrity();
+            for check in &report.files {
+                if check.is_ok() {
+                    println!("OK    {}", check.path);
+                } else {
+                    for issue in &check.issues {
+                        println!("FAIL  {}: {}", check.path, issue);
+                    }
+                }
+            }
+
+            let failed = report.failed().count();
+            if failed > 0 {
+                println!("{} of {} files failed verification.", failed, report.files.len());
+                std::process::exit(1);
+            }
+            if !signature_ok {
+                println!("Signature check failed.");
+                std::process::exit(1);
+            }
+            println!("All files verified.");
+        }
+        Commands::Unpack { pack, output, verify } => {
+            let mut loaded = open_pack(&pack, keys.as_ref())?;
+            if verify {
+                loaded.set_verify_policy(VerifyPolicy::Always);
+            }
             println!("Unpacking {} to {:?}...", pack.display(), output);
 
-            let files = loaded.file_list();
-            for path in files {
-                let content = loaded.read_file(&path)?;
-                let output_path = output.join(&path);
+            for file in loaded.files() {
+                let path = &file.path;
+                // Keep patches distinct from whole files so a repack reads them back as patches
+                let name = if file.is_merge_patch() { format!("{path}{MERGE_PATCH_SUFFIX}") } else { path.clone() };
+                let output_path = output.join(&name);
                 
                 if let Some(parent) = output_path.parent() {
                     std::fs::create_dir_all(parent)?;
                 }
                 
-                std::fs::write(&output_path, content)?;
-                println!("Extracted: {}", path);
+                let mut entry = loaded.open_file(path)?;
+                std::io::copy(&mut entry, &mut std::fs::File::create(&output_path)?)?;
+                println!("Extracted: {}", name);
+            }
+            let deleted = loaded.deleted_paths();
+            if !deleted.is_empty() {
+                std::fs::create_dir_all(&output)?;
+                std::fs::write(output.join(DELETE_LIST), deleted.join("\n") + "\n")?;
+                println!("Wrote {} deletion(s) to {}", deleted.len(), DELETE_LIST);
             }
             println!("Unpack complete.");
         }
@@ -0,0 +1,461 @@
+//! Non-blocking counterparts of `LoadedPack` and `Resolver` for tokio.
+//!
+//! Header parsing, index decoding, key handling and entry decoding are the
+//! same code the sync types use (`PackState`). Fetching byte ranges is async,
+//! and key derivation, decryption and decompression run on tokio's blocking
+//! pool, so neither file I/O nor CPU-heavy decoding stalls the executor.
+
+use std::borrow::Cow;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+use tokio::sync::Mutex;
+use crate::crypto::{self, KeyProvider};
+use crate::error::Result;
+use crate::format::{CompressionType, EncryptionType, FileEntry, PackManifest};
+use super::source::{self, PackSource};
+use super::patch;
+use super::stack::{self, Layer, LayerStack, PackHandle, PriorityConflict};
+use super::state::{self, Header, PackState, PREFIX_LEN};
+use super::{
+    AuthStatus, CacheStats, DirEntry, Metadata, ResolvedEntry, SignatureInfo, TrustPolicy, VerifyPolicy,
+};
+
+pub type SourceFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+/// Runs CPU-heavy work on tokio's blocking thread pool.
+async fn run_blocking<T, F>(work: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(work).await.map_err(io::Error::other)?
+}
+
+/// Async counterpart of `PackSource`: fetches byte ranges of a pack.
+pub trait AsyncPackSource: Send + Sync {
+    /// Reads exactly `len` bytes starting at `offset`.
+    fn read_at(&self, offset: u64, len: usize) -> SourceFuture<'_, Vec<u8>>;
+
+    /// Total size of the source in bytes.
+    fn size(&self) -> SourceFuture<'_, u64>;
+}
+
+/// Runs a blocking `PackSource` on tokio's blocking thread pool, so file
+/// reads never stall the executor. Sources held in memory are read inline.
+pub struct BlockingSource(Arc<dyn PackSource>);
+
+impl BlockingSource {
+    pub fn new(source: Arc<dyn PackSource>) -> Self {
+        Self(source)
+    }
+}
+
+impl AsyncPackSource for BlockingSource {
+    fn read_at(&self, offset: u64, len: usize) -> SourceFuture<'_, Vec<u8>> {
+        let source = self.0.clone();
+        Box::pin(async move {
+            if let Some(bytes) = source.as_slice() {
+                return source::slice_at(bytes, offset, len)
+                    .map(<[u8]>::to_vec)
+                    .ok_or_else(|| io::ErrorKind::UnexpectedEof.into());
+            }
+            tokio::task::spawn_blocking(move || {
+                let mut buf = vec![0u8; len];
+                source.read_exact_at(&mut buf, offset)?;
+                Ok(buf)
+            })
+            .await
+            .map_err(io::Error::other)?
+        })
+    }
+
+    fn size(&self) -> SourceFuture<'_, u64> {
+        let source = self.0.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || source.size())
+                .await
+                .map_err(io::Error::other)?
+        })
+    }
+}
+
+/// Adapts an `AsyncRead + AsyncSeek` reader. Reads take turns on a lock,
+/// since each one has to seek the shared reader first.
+pub struct AsyncReaderSource<R>(Mutex<R>);
+
+impl<R> AsyncReaderSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self(Mutex::new(reader))
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin + Send> AsyncPackSource for AsyncReaderSource<R> {
+    fn read_at(&self, offset: u64, len: usize) -> SourceFuture<'_, Vec<u8>> {
+        Box::pin(async move {
+            let mut reader = self.0.lock().await;
+            reader.seek(io::SeekFrom::Start(offset)).await?;
+            let mut buf = vec![0u8; len];
+            reader.read_exact(&mut buf).await?;
+            Ok(buf)
+        })
+    }
+
+    fn size(&self) -> SourceFuture<'_, u64> {
+        Box::pin(async move { self.0.lock().await.seek(io::SeekFrom::End(0)).await })
+    }
+}
+
+/// Async counterpart of `LoadedPack`.
+pub struct AsyncLoadedPack {
+    source: Box<dyn AsyncPackSource>,
+    pub manifest: PackManifest,
+    state: PackState,
+}
+
+impl AsyncLoadedPack {
+    /// Loads a pack using the default key source, like `LoadedPack::load`.
+    pub async fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::load_with_key_provider(path, crypto::default_key_provider()).await
+    }
+
+    pub async fn load_with_key<P: AsRef<Path>>(path: P, secret: &[u8]) -> Result<Self> {
+        Self::load_with_key_provider(path, Some(&crypto::StaticKey::new(secret))).await
+    }
+
+    pub async fn load_with_key_provider<P: AsRef<Path>>(path: P, keys: Option<&dyn KeyProvider>) -> Result<Self> {
+        let path_str = path.as_ref().to_string_lossy().to_string();
+        let file = tokio::fs::File::open(path).await?.into_std().await;
+        let source = BlockingSource::new(Arc::from(source::file_source(file)));
+        Self::load_from_source_with_key_provider(Box::new(source), path_str, keys).await
+    }
+
+    pub async fn load_from_memory(data: Vec<u8>) -> Result<Self> {
+        Self::load_from_memory_with_key_provider(data, crypto::default_key_provider()).await
+    }
+
+    pub async fn load_from_memory_with_key_provider(data: Vec<u8>, keys: Option<&dyn KeyProvider>) -> Result<Self> {
+        let source = BlockingSource::new(Arc::new(data));
+        Self::load_from_source_with_key_provider(Box::new(source), "memory".to_string(), keys).await
+    }
+
+    pub async fn load_from_reader<R>(reader: R, source_info: String) -> Result<Self>
+    where
+        R: AsyncRead + AsyncSeek + Unpin + Send + 'static,
+    {
+        let source = AsyncReaderSource::new(reader);
+        Self::load_from_source_with_key_provider(Box::new(source), source_info, crypto::default_key_provider()).await
+    }
+
+    pub async fn load_from_source_with_key_provider(
+        source: Box<dyn AsyncPackSource>,
+        source_info: String,
+        keys: Option<&dyn KeyProvider>,
+    ) -> Result<Self> {
+        let source_len = source.size().await?;
+        let prefix: [u8; PREFIX_LEN] = source.read_at(0, PREFIX_LEN).await?
+            .try_into()
+            .map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        let (version, rest_len) = state::parse_prefix(&prefix, source_len)?;
+
+        let rest = source.read_at(PREFIX_LEN as u64, rest_len).await?;
+        let header = Header::parse(version, rest, source_len)?;
+
+        let index_buf = source.read_at(header.index_offset, header.index_len).await?;
+        let trailer_buf = source.read_at(header.index_end(), header.trailer_len(source_len)?).await?;
+
+        let (manifest, state) = PackState::new(header, &index_buf, &trailer_buf, source_info)?;
+        let mut pack = Self { source, manifest, state };
+        if let Some(keys) = keys {
+            pack.set_key_provider(keys).await?;
+        }
+        Ok(pack)
+    }
+
+    /// See `LoadedPack::set_key`. The key is derived on the blocking pool,
+    /// since Argon2id takes tens of milliseconds by design.
+    pub async fn set_key(&mut self, secret: &[u8]) -> Result<()> {
+        let kdf = self.state.kdf().cloned();
+        let secret = secret.to_vec();
+        let key = run_blocking(move || state::derive_pack_key(kdf.as_ref(), &secret)).await?;
+        self.state.install_key(key)
+    }
+
+    /// Asks `keys` for this pack's secret; keeps the current key if it has none.
+    pub async fn set_key_provider(&mut self, keys: &dyn KeyProvider) -> Result<()> {
+        match keys.key_for(&self.manifest) {
+            Some(secret) => self.set_key(&secret).await,
+            None => Ok(()),
+        }
+    }
+
+    pub fn has_key(&self) -> bool {
+        self.state.has_key()
+    }
+
+    pub fn auth_status(&self) -> AuthStatus {
+        self.state.auth_status
+    }
+
+    pub fn signature(&self) -> Option<SignatureInfo> {
+        self.state.signature
+    }
+
+    pub fn signer(&self) -> Option<[u8; 32]> {
+        self.state.signature.filter(|s| s.valid).map(|s| s.signer)
+    }
+
+    pub fn format_version(&self) -> u32 {
+        self.state.version
+    }
+
+    pub fn verify_policy(&self) -> VerifyPolicy {
+        self.state.verify_policy
+    }
+
+    pub fn set_verify_policy(&mut self, policy: VerifyPolicy) {
+        self.state.set_verify_policy(policy);
+    }
+
+    /// See `LoadedPack::set_case_insensitive`.
+    pub fn set_case_insensitive(&mut self, enabled: bool) {
+        self.state.set_case_insensitive(enabled);
+    }
+
+    pub fn is_case_insensitive(&self) -> bool {
+        self.state.is_case_insensitive()
+    }
+
+    pub fn get_entry(&self, path: &str) -> Option<&FileEntry> {
+        self.state.lookup(path)
+    }
+
+    pub fn file_list(&self) -> Vec<String> {
+        self.files().map(|e| e.path.clone()).collect()
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &FileEntry> {
+        self.state.files()
+    }
+
+    /// See `LoadedPack::read_dir`.
+    pub fn read_dir(&self, dir: &str) -> Vec<DirEntry> {
+        self.state.read_dir(dir)
+    }
+
+    pub fn glob(&self, pattern: &str) -> Result<Vec<String>> {
+        self.state.glob(pattern)
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        self.metadata(path).is_some()
+    }
+
+    pub fn metadata(&self, path: &str) -> Option<Metadata> {
+        self.state.metadata(path)
+    }
+
+    pub fn deleted_paths(&self) -> Vec<String> {
+        self.state.entries.values().filter(|e| e.is_tombstone()).map(|e| e.path.clone()).collect()
+    }
+
+    pub async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        let entry = self.state.readable_entry(path)?;
+        let verify = self.state.should_verify(&entry);
+        let raw_data = self.source.read_at(entry.offset, entry.compressed_size as usize).await?;
+        // Nothing to decode: skip the round trip to the blocking pool
+        if !verify && entry.compression == CompressionType::Store && entry.encryption == EncryptionType::None {
+            return Ok(raw_data);
+        }
+        let decoder = self.state.decoder();
+        let (entry, data) = run_blocking(move || {
+            let data = decoder.decode(&entry, Cow::Owned(raw_data), verify)?.into_owned();
+            Ok((entry, data))
+        })
+        .await?;
+        self.state.record_verified(&entry, verify);
+        Ok(data)
+    }
+}
+
+impl Layer for AsyncLoadedPack {
+    fn manifest(&self) -> &PackManifest {
+        &self.manifest
+    }
+
+    fn state(&self) -> &PackState {
+        &self.state
+    }
+
+    fn state_mut(&mut self) -> &mut PackState {
+        &mut self.state
+    }
+}
+
+/// Async counterpart of `Resolver`, with the same layering rules.
+#[derive(Default)]
+pub struct AsyncResolver {
+    stack: LayerStack<AsyncLoadedPack>,
+}
+
+impl AsyncResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_key_provider(keys: Arc<dyn KeyProvider>) -> Self {
+        let mut resolver = Self::default();
+        resolver.stack.key_provider = Some(keys);
+        resolver
+    }
+
+    /// See `Resolver::set_trust_policy`.
+    pub fn set_trust_policy(&mut self, policy: TrustPolicy) -> Result<()> {
+        self.stack.set_trust_policy(policy)
+    }
+
+    /// See `Resolver::set_key_provider`. Keys are derived on the blocking pool.
+    pub async fn set_key_provider(&mut self, keys: Arc<dyn KeyProvider>) -> Result<()> {
+        for slot in &mut self.stack.packs {
+            if !slot.pack.has_key() {
+                slot.pack.set_key_provider(keys.as_ref()).await?;
+            }
+        }
+        self.stack.set_key_provider(keys)
+    }
+
+    pub async fn load_pack<P: AsRef<Path>>(&mut self, path: P) -> Result<PackHandle> {
+        let keys = self.stack.key_provider.clone();
+        let pack = match &keys {
+            Some(keys) => AsyncLoadedPack::load_with_key_provider(path, Some(keys.as_ref())).await?,
+            None => AsyncLoadedPack::load(path).await?,
+        };
+        self.add_pack(pack).await
+    }
+
+    /// See `Resolver::add_pack`. A pack without a key gets one from the key
+    /// provider first, derived on the blocking pool.
+    pub async fn add_pack(&mut self, mut pack: AsyncLoadedPack) -> Result<PackHandle> {
+        if let Some(keys) = self.stack.key_provider.clone().filter(|_| !pack.has_key()) {
+            pack.set_key_provider(keys.as_ref()).await?;
+        }
+        self.stack.add(pack)
+    }
+
+    pub fn remove_pack(&mut self, handle: PackHandle) -> Option<AsyncLoadedPack> {
+        self.stack.remove(handle)
+    }
+
+    /// See `Resolver::set_priority`.
+    pub fn set_priority(&mut self, handle: PackHandle, priority: i32) -> Result<bool> {
+        self.stack.set_priority(handle, priority)
+    }
+
+    pub fn set_enabled(&mut self, handle: PackHandle, enabled: bool) -> bool {
+        self.stack.set_enabled(handle, enabled)
+    }
+
+    pub fn pack(&self, handle: PackHandle) -> Option<&AsyncLoadedPack> {
+        self.stack.get(handle)
+    }
+
+    /// See `Resolver::set_case_insensitive`.
+    pub fn set_case_insensitive(&mut self, enabled: bool) {
+        self.stack.set_case_insensitive(enabled);
+    }
+
+    /// See `Resolver::set_locale`.
+    pub fn set_locale(&mut self, locale: &str) {
+        self.stack.set_locale_chain(stack::locale_chain(locale));
+    }
+
+    pub fn set_locale_chain(&mut self, chain: Vec<String>) {
+        self.stack.set_locale_chain(chain);
+    }
+
+    pub fn locale(&self) -> &[String] {
+        self.stack.locale()
+    }
+
+    /// See `Resolver::list_dir`.
+    pub fn list_dir(&self, dir: &str) -> Vec<ResolvedEntry> {
+        self.stack.list_dir(dir)
+    }
+
+    /// See `Resolver::glob`.
+    pub fn glob(&self, pattern: &str) -> Result<Vec<ResolvedEntry>> {
+        self.stack.glob(pattern)
+    }
+
+    /// See `Resolver::priority_conflicts`.
+    pub fn priority_conflicts(&self) -> Vec<PriorityConflict> {
+        self.stack.priority_conflicts()
+    }
+
+    pub async fn resolve(&self, path: &str) -> Option<Vec<u8>> {
+        self.try_resolve(path).await.ok().flatten()
+    }
+
+    /// See `Resolver::try_resolve`.
+    pub async fn try_resolve(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.try_resolve_shared(path).await?.map(|data| data.to_vec()))
+    }
+
+    /// See `Resolver::resolve_shared`.
+    pub async fn resolve_shared(&self, path: &str) -> Option<Arc<[u8]>> {
+        self.try_resolve_shared(path).await.ok().flatten()
+    }
+
+    /// See `Resolver::try_resolve_shared`.
+    pub async fn try_resolve_shared(&self, path: &str) -> Result<Option<Arc<[u8]>>> {
+        let Some(resolution) = self.stack.resolution(path) else {
+            return Ok(None);
+        };
+        // Cache under the stored path, however the caller spelled it
+        let (top, entry) = resolution.top();
+        if let Some(data) = self.stack.cached(top.id, &entry.path) {
+            return Ok(Some(data));
+        }
+        let (base, base_entry) = resolution.base;
+        let base_data = base.pack.read_file(&base_entry.path).await?;
+        let data: Arc<[u8]> = if resolution.patches.is_empty() {
+            base_data.into()
+        } else {
+            let mut patches = Vec::with_capacity(resolution.patches.len());
+            for (slot, patch) in resolution.patches.iter().rev() {
+                patches.push(slot.pack.read_file(&patch.path).await?);
+            }
+            // Parsing and merging JSON is CPU-bound, like decoding
+            let path = entry.path.clone();
+            run_blocking(move || patch::compose(&path, &base_data, patches.iter().map(Vec::as_slice)))
+                .await?
+                .into()
+        };
+        self.stack.store(top.id, &entry.path, data.clone());
+        Ok(Some(data))
+    }
+
+    /// See `Resolver::set_cache_capacity`.
+    pub fn set_cache_capacity(&mut self, max_bytes: usize) {
+        self.stack.set_cache_capacity(max_bytes);
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.stack.cache_stats()
+    }
+
+    pub fn evict(&self, path: &str) -> bool {
+        self.stack.evict(path)
+    }
+
+    pub fn clear_cache(&self) {
+        self.stack.clear_cache();
+    }
+
+    pub fn list_layers(&self, path: &str) -> Vec<String> {
+        self.stack.list_layers(path)
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// Counters reported by `Resolver::cache_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped to make room (not counting explicit eviction).
+    pub evictions: u64,
+    pub entries: usize,
+    /// Decoded bytes currently held.
+    pub bytes: usize,
+    pub capacity: usize,
+}
+
+struct Cached {
+    /// Pack that served the data; a different winner means the entry is stale.
+    pack: u64,
+    data: Arc<[u8]>,
+    last_used: u64,
+}
+
+/// Decoded assets, least recently used evicted first once `capacity` bytes
+/// are exceeded.
+pub(crate) struct AssetCache {
+    entries: HashMap<String, Cached>,
+    /// `last_used` tick -> path, oldest first.
+    order: BTreeMap<u64, String>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl AssetCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            stats: CacheStats { capacity, ..CacheStats::default() },
+        }
+    }
+
+    pub fn get(&mut self, pack: u64, path: &str) -> Option<Arc<[u8]>> {
+        self.tick += 1;
+        let tick = self.tick;
+        match self.entries.get_mut(path) {
+            Some(cached) if cached.pack == pack => {
+                self.order.remove(&cached.last_used);
+                self.order.insert(tick, path.to_string());
+                cached.last_used = tick;
+                self.stats.hits += 1;
+                Some(cached.data.clone())
+            }
+            _ => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, pack: u64, path: &str, data: Arc<[u8]>) {
+        self.remove(path);
+        // Never worth flushing the whole cache for one oversized asset
+        if data.len() > self.stats.capacity {
+            return;
+        }
+        while self.stats.bytes + data.len() > self.stats.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else { break };
+            if let Some(old) = self.entries.remove(&oldest) {
+                self.stats.bytes -= old.data.len();
+                self.stats.evictions += 1;
+            }
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, path.to_string());
+        self.stats.bytes += data.len();
+        self.entries.insert(path.to_string(), Cached { pack, data, last_used: self.tick });
+        self.stats.entries = self.entries.len();
+    }
+
+    pub fn remove(&mut self, path: &str) -> bool {
+        let Some(old) = self.entries.remove(path) else { return false };
+        self.order.remove(&old.last_used);
+        self.stats.bytes -= old.data.len();
+        self.stats.entries = self.entries.len();
+        true
+    }
+
+    /// Drops every entry; statistics are kept.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.stats.bytes = 0;
+        self.stats.entries = 0;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn asset(len: usize) -> Arc<[u8]> {
+        vec![0u8; len].into()
+    }
+
+    fn cache_with(capacity: usize, paths: &[(&str, usize)]) -> AssetCache {
+        let mut cache = AssetCache::new(capacity);
+        for (path, len) in paths {
+            cache.insert(1, path, asset(*len));
+        }
+        cache
+    }
+
+    #[test]
+    fn evicts_least_recently_used_first() {
+        let mut cache = cache_with(30, &[("a", 10), ("b", 10), ("c", 10)]);
+        // Touching `a` leaves `b` as the oldest
+        assert!(cache.get(1, "a").is_some());
+        cache.insert(1, "d", asset(10));
+
+        assert!(cache.get(1, "b").is_none());
+        for path in ["a", "c", "d"] {
+            assert!(cache.get(1, path).is_some(), "{path}");
+        }
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.bytes, stats.evictions), (3, 30, 1));
+    }
+
+    #[test]
+    fn counts_hits_and_misses_per_pack() {
+        let mut cache = cache_with(30, &[("a", 10)]);
+        assert!(cache.get(1, "a").is_some());
+        // A different winning pack makes the entry stale
+        assert!(cache.get(2, "a").is_none());
+        assert!(cache.get(1, "missing").is_none());
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+    }
+
+    #[test]
+    fn remove_releases_its_bytes() {
+        let mut cache = cache_with(30, &[("a", 10), ("b", 5)]);
+        assert!(cache.remove("a"));
+        assert!(!cache.remove("a"));
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.bytes, stats.evictions), (1, 5, 0));
+
+        // The freed room is usable without evicting `b`
+        cache.insert(1, "c", asset(25));
+        assert!(cache.get(1, "b").is_some());
+        assert_eq!(cache.stats().evictions, 0);
+    }
+
+    #[test]
+    fn insert_over_an_existing_key_replaces_its_bytes() {
+        let mut cache = cache_with(30, &[("a", 10), ("b", 10)]);
+        cache.insert(2, "a", asset(20));
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.bytes, stats.evictions), (2, 30, 0));
+        assert_eq!(cache.get(2, "a").unwrap().len(), 20);
+        assert!(cache.get(1, "a").is_none());
+    }
+
+    #[test]
+    fn skips_assets_larger_than_the_capacity() {
+        let mut cache = cache_with(30, &[("a", 10), ("b", 10)]);
+        cache.insert(1, "huge", asset(31));
+        assert!(cache.get(1, "huge").is_none());
+        assert!(cache.get(1, "a").is_some() && cache.get(1, "b").is_some());
+        assert_eq!(cache.stats().evictions, 0);
+
+        // An oversized replacement still drops the old data
+        cache.insert(1, "a", asset(31));
+        assert!(cache.get(1, "a").is_none());
+        assert_eq!((cache.stats().entries, cache.stats().bytes), (1, 10));
+    }
+
+    #[test]
+    fn counts_every_entry_evicted_for_one_insert() {
+        let mut cache = cache_with(30, &[("a", 10), ("b", 10), ("c", 10)]);
+        cache.insert(1, "d", asset(25));
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.bytes, stats.evictions), (1, 25, 3));
+
+        cache.clear();
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.bytes, stats.evictions), (0, 0, 3));
+    }
+}
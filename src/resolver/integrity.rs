@@ -0,0 +1,123 @@
+use thiserror::Error;
+use crate::error::LayerPackError;
+use super::LoadedPack;
+
+/// A problem found by `LoadedPack::check_integrity`.
+#[derive(Debug, Error)]
+pub enum IntegrityIssue {
+    #[error("data range {start}..{end} is outside the data region {data_start}..{data_end}")]
+    OutOfBounds { start: u64, end: u64, data_start: u64, data_end: u64 },
+    #[error("data overlaps '{other}'")]
+    Overlap { other: String },
+    #[error("path appears more than once in the index")]
+    Duplicate,
+    #[error("decoded {actual} bytes, index says {expected}")]
+    SizeMismatch { expected: u64, actual: u64 },
+    #[error("{0}")]
+    Read(LayerPackError),
+}
+
+#[derive(Debug)]
+pub struct FileCheck {
+    pub path: String,
+    pub issues: Vec<IntegrityIssue>,
+}
+
+impl FileCheck {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Result of a full-pack check, one `FileCheck` per index entry in path order.
+#[derive(Debug)]
+pub struct IntegrityReport {
+    pub files: Vec<FileCheck>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.files.iter().all(FileCheck::is_ok)
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &FileCheck> {
+        self.files.iter().filter(|f| !f.is_ok())
+    }
+}
+
+impl LoadedPack {
+    /// Decodes every entry and checks it against its hash, and checks that the
+    /// index itself is sane: every entry lies inside the data region, no two
+    /// entries share bytes, and no path is listed twice.
+    pub fn check_integrity(&self) -> IntegrityReport {
+        let layout = self.state.layout;
+        let mut files: Vec<FileCheck> = self
+            .file_list()
+            .into_iter()
+            .map(|path| FileCheck { path, issues: Vec::new() })
+            .collect();
+
+        // Bounds
+        for check in &mut files {
+            let entry = &self.state.entries[&check.path];
+            let end = entry.offset.checked_add(entry.compressed_size);
+            if entry.offset < layout.data_start || end.is_none_or(|end| end > layout.index_offset) {
+                check.issues.push(IntegrityIssue::OutOfBounds {
+                    start: entry.offset,
+                    end: end.unwrap_or(u64::MAX),
+                    data_start: layout.data_start,
+                    data_end: layout.index_offset,
+                });
+            }
+        }
+
+        // Overlaps: walk entries in offset order and compare each with the furthest end seen so far.
+        let mut by_offset: Vec<usize> = (0..files.len())
+            .filter(|&i| self.state.entries[&files[i].path].compressed_size > 0)
+            .collect();
+        by_offset.sort_by_key(|&i| self.state.entries[&files[i].path].offset);
+        let mut furthest: Option<(usize, u64)> = None;
+        for i in by_offset {
+            let entry = &self.state.entries[&files[i].path];
+            let end = entry.offset.saturating_add(entry.compressed_size);
+            if let Some((prev, prev_end)) = furthest {
+                if entry.offset < prev_end {
+                    let other = files[prev].path.clone();
+                    files[i].issues.push(IntegrityIssue::Overlap { other });
+                }
+                if end <= prev_end {
+                    continue;
+                }
+            }
+            furthest = Some((i, end));
+        }
+
+        // Duplicates
+        for check in &mut files {
+            if self.state.duplicates.contains(&check.path) {
+                check.issues.push(IntegrityIssue::Duplicate);
+            }
+        }
+
+        // Content: only read entries whose range is valid, so a bad offset
+        // cannot make us allocate or seek past the end of the source.
+        for check in &mut files {
+            if check.issues.iter().any(|i| matches!(i, IntegrityIssue::OutOfBounds { .. })) {
+                continue;
+            }
+            let expected = self.state.entries[&check.path].original_size;
+            // Always hash, whatever the read policy (v1 entries may have no hash)
+            let data = self.state.readable_entry(&check.path)
+                .and_then(|entry| self.decode_file(&entry, !entry.hash.is_empty()));
+            match data {
+                Ok(data) if data.len() as u64 != expected => {
+                    check.issues.push(IntegrityIssue::SizeMismatch { expected, actual: data.len() as u64 });
+                }
+                Ok(_) => {}
+                Err(e) => check.issues.push(IntegrityIssue::Read(e)),
+            }
+        }
+
+        IntegrityReport { files }
+    }
+}
@@ -0,0 +1,275 @@
+//! Path queries over sorted indexes: directory prefixes and globs.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use globset::{GlobBuilder, GlobMatcher};
+use crate::error::{LayerPackError, Result};
+use crate::format::FileEntry;
+use crate::format::path as pack_path;
+use super::state::PackState;
+use super::PackHandle;
+
+/// A path in the merged view of a `Resolver`, with the pack serving it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedEntry {
+    pub path: String,
+    pub pack: PackHandle,
+}
+
+/// An immediate child of a directory in one pack. Packs store only files;
+/// directories are implied by the paths under them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// Full path inside the pack, without a trailing `/` for directories.
+    pub path: String,
+    pub is_dir: bool,
+}
+
+impl DirEntry {
+    /// The last path segment.
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+}
+
+/// What a path names in one pack, like `std::fs::Metadata`.
+#[derive(Debug, Clone)]
+pub enum Metadata {
+    File(FileEntry),
+    Dir,
+}
+
+impl Metadata {
+    pub fn is_file(&self) -> bool {
+        matches!(self, Self::File(_))
+    }
+
+    pub fn is_dir(&self) -> bool {
+        matches!(self, Self::Dir)
+    }
+
+    /// Decoded size of a file; 0 for a directory.
+    pub fn len(&self) -> u64 {
+        match self {
+            Self::File(entry) => entry.original_size,
+            Self::Dir => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn entry(&self) -> Option<&FileEntry> {
+        match self {
+            Self::File(entry) => Some(entry),
+            Self::Dir => None,
+        }
+    }
+}
+
+/// `lang/th`, `./lang/th/` and `lang\\th` all become `lang/th/`; the root
+/// stays empty.
+pub(crate) fn dir_prefix(dir: &str) -> String {
+    let dir = pack_path::normalize(dir);
+    if dir.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", dir)
+    }
+}
+
+/// A normalized path or prefix in the form index keys use.
+pub(crate) fn index_key(path: String, case_insensitive: bool) -> String {
+    if case_insensitive {
+        path.to_lowercase()
+    } else {
+        path
+    }
+}
+
+/// Normalizes the literal directories of a glob, everything before the first
+/// wildcard, the way lookups normalize paths: `./data\*.json` becomes
+/// `data/*.json`. The rest is left alone, since `\` escapes there.
+pub(crate) fn normalize_glob(pattern: &str) -> String {
+    let literal_end = pattern.find(['*', '?', '[', '{']).unwrap_or(pattern.len());
+    let dir_end = pattern[..literal_end].rfind(['/', '\\']).map_or(0, |i| i + 1);
+    let dir = pack_path::normalize(&pattern[..dir_end]);
+    let rest = &pattern[dir_end..];
+    if dir.is_empty() {
+        rest.to_string()
+    } else {
+        format!("{}/{}", dir, rest)
+    }
+}
+
+/// `*`, `?` and `[...]` stay within one path segment; `**` crosses them.
+/// `pattern` should already be in `normalize_glob` form.
+pub(crate) fn compile_glob(pattern: &str, case_insensitive: bool) -> Result<GlobMatcher> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .case_insensitive(case_insensitive)
+        .build()
+        .map(|glob| glob.compile_matcher())
+        .map_err(|e| LayerPackError::InvalidPattern(e.to_string()))
+}
+
+/// The directories before the first wildcard, e.g. `maps/` for
+/// `maps/*.json`, so only that part of the index has to be matched.
+pub(crate) fn glob_prefix(pattern: &str) -> &str {
+    let literal = pattern.find(['*', '?', '[', '{', '\\']).map_or(pattern, |i| &pattern[..i]);
+    literal.rfind('/').map_or("", |i| &pattern[..=i])
+}
+
+/// Entries of `map` whose key starts with `prefix`, in order.
+pub(crate) fn with_prefix<'a, V>(
+    map: &'a BTreeMap<String, V>,
+    prefix: &'a str,
+) -> impl Iterator<Item = (&'a String, &'a V)> {
+    map.range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+        .take_while(move |(path, _)| path.starts_with(prefix))
+}
+
+impl PackState {
+    pub(crate) fn set_case_insensitive(&mut self, enabled: bool) {
+        if enabled == self.folded.is_some() {
+            return;
+        }
+        self.folded = enabled.then(|| {
+            let mut folded = BTreeMap::new();
+            // Paths differing only in case: the first in sorted order wins
+            for path in self.entries.keys() {
+                folded.entry(pack_path::fold_case(path)).or_insert_with(|| path.clone());
+            }
+            folded
+        });
+    }
+
+    pub(crate) fn is_case_insensitive(&self) -> bool {
+        self.folded.is_some()
+    }
+
+    /// Finds the entry for `path` after normalizing it; in case-insensitive
+    /// mode an exact match still takes precedence. Includes tombstones.
+    pub(crate) fn lookup(&self, path: &str) -> Option<&FileEntry> {
+        let path = pack_path::normalize(path);
+        if let Some(entry) = self.entries.get(path.as_ref()) {
+            return Some(entry);
+        }
+        let stored = self.folded.as_ref()?.get(&pack_path::fold_case(&path))?;
+        self.entries.get(stored)
+    }
+
+    /// Entries whose index key starts with `prefix`, in key order. Keys are
+    /// the stored paths, or their folded form in case-insensitive mode, so
+    /// `prefix` has to be in the same form. Includes tombstones.
+    pub(crate) fn index_under<'a>(&'a self, prefix: &'a str) -> Box<dyn Iterator<Item = (&'a str, &'a FileEntry)> + 'a> {
+        match &self.folded {
+            Some(folded) => Box::new(
+                with_prefix(folded, prefix).map(|(key, stored)| (key.as_str(), &self.entries[stored])),
+            ),
+            None => Box::new(with_prefix(&self.entries, prefix).map(|(key, entry)| (key.as_str(), entry))),
+        }
+    }
+
+    /// File entries in path order, without tombstones.
+    pub(crate) fn files(&self) -> impl Iterator<Item = &FileEntry> {
+        self.entries.values().filter(|entry| !entry.is_tombstone())
+    }
+
+    fn files_under<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a str, &'a FileEntry)> {
+        self.index_under(prefix).filter(|(_, entry)| !entry.is_tombstone())
+    }
+
+    pub(crate) fn read_dir(&self, dir: &str) -> Vec<DirEntry> {
+        let prefix = index_key(dir_prefix(dir), self.is_case_insensitive());
+        let depth = prefix.matches('/').count();
+        let mut children: Vec<DirEntry> = Vec::new();
+        let mut last_dir = None;
+        for (key, entry) in self.files_under(&prefix) {
+            match key[prefix.len()..].find('/') {
+                Some(i) => {
+                    // Keys under one subdirectory are adjacent in sorted order
+                    let dir_key = &key[..prefix.len() + i];
+                    if last_dir != Some(dir_key) {
+                        last_dir = Some(dir_key);
+                        // Segment-wise, since case folding may change byte lengths
+                        let path = entry.path.split('/').take(depth + 1).collect::<Vec<_>>().join("/");
+                        children.push(DirEntry { path, is_dir: true });
+                    }
+                }
+                None => children.push(DirEntry { path: entry.path.clone(), is_dir: false }),
+            }
+        }
+        children
+    }
+
+    pub(crate) fn glob(&self, pattern: &str) -> Result<Vec<String>> {
+        let case_insensitive = self.is_case_insensitive();
+        let pattern = &normalize_glob(pattern);
+        let matcher = compile_glob(pattern, case_insensitive)?;
+        let prefix = index_key(glob_prefix(pattern).to_string(), case_insensitive);
+        Ok(self
+            .files_under(&prefix)
+            .filter(|(_, entry)| matcher.is_match(&entry.path))
+            .map(|(_, entry)| entry.path.clone())
+            .collect())
+    }
+
+    pub(crate) fn metadata(&self, path: &str) -> Option<Metadata> {
+        match self.lookup(path) {
+            Some(entry) if !entry.is_tombstone() => Some(Metadata::File(entry.clone())),
+            _ => {
+                let prefix = index_key(dir_prefix(path), self.is_case_insensitive());
+                let is_dir = prefix.is_empty() || self.files_under(&prefix).next().is_some();
+                is_dir.then_some(Metadata::Dir)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_glob_cleans_only_the_literal_directories() {
+        let cases = [
+            ("./data\\*.json", "data/*.json"),
+            ("/img//sub/../**/*.png", "img/**/*.png"),
+            ("*.json", "*.json"),
+            ("data/Map001.json", "data/Map001.json"),
+            // After the first wildcard `\` is an escape, not a separator
+            ("maps/*\\[1\\].json", "maps/*\\[1\\].json"),
+            ("./maps\\sub/*\\*", "maps/sub/*\\*"),
+        ];
+        for (pattern, expected) in cases {
+            assert_eq!(normalize_glob(pattern), expected, "{pattern}");
+        }
+    }
+
+    #[test]
+    fn escapes_after_a_wildcard_match_literally() {
+        let matcher = compile_glob(&normalize_glob("./maps\\*\\[1\\].json"), false).unwrap();
+        assert!(matcher.is_match("maps/Map[1].json"));
+        assert!(!matcher.is_match("maps/Map1.json"));
+    }
+
+    #[test]
+    fn glob_prefix_stops_at_the_first_wildcard_or_escape() {
+        let cases = [
+            ("maps/*.json", "maps/"),
+            ("maps/sub/Map00?.json", "maps/sub/"),
+            ("maps/{a,b}/x.json", "maps/"),
+            ("maps/Map001.json", "maps/"),
+            ("*.json", ""),
+            ("**/*.png", ""),
+            // An escape could hide a `/` meant literally, so the prefix stops there
+            ("maps/a\\*b/*.json", "maps/"),
+            ("maps/sub/*\\[1\\].json", "maps/sub/"),
+        ];
+        for (pattern, expected) in cases {
+            assert_eq!(glob_prefix(pattern), expected, "{pattern}");
+        }
+    }
+}
@@ -1,172 +1,553 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
 use std::fs::File;
 use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::path::Path;
-use anyhow::anyhow;
-use crate::format::{PackManifest, FileEntry, CompressionType};
-use sha2::Digest;
-use aes_gcm::{Aes256Gcm, Key, Nonce};
-use aes_gcm::aead::{Aead, KeyInit};
+use std::sync::Arc;
+use crate::error::Result;
+use crate::format::{chunked, PackManifest, FileEntry, EncryptionType};
+use crate::crypto::{self, KeyProvider};
 
-// ดึงรหัสผ่านมาจาก Environment ตอน build
-const ENCRYPTION_KEY: &str = env!("LPACK_ENCRYPTION_KEY");
+#[cfg(feature = "async")]
+mod async_pack;
+mod cache;
+mod integrity;
+mod listing;
+mod patch;
+mod source;
+mod stack;
+mod state;
+mod stream;
+mod trust;
+#[cfg(feature = "async")]
+pub use async_pack::{AsyncLoadedPack, AsyncPackSource, AsyncReaderSource, AsyncResolver, BlockingSource, SourceFuture};
+pub use cache::CacheStats;
+pub use integrity::{IntegrityIssue, IntegrityReport, FileCheck};
+pub use listing::{DirEntry, Metadata, ResolvedEntry};
+pub use patch::merge_patch;
+pub use source::{LockedReader, PackSource};
+pub use stack::{PackHandle, PriorityConflict};
+#[cfg(feature = "mmap")]
+pub use source::MmapSource;
+pub use stream::EntryReader;
+pub use trust::TrustPolicy;
+use stack::{Layer, LayerStack};
+use state::{check_hash, EntryDecoder, Header, PackState, PREFIX_LEN};
+use stream::{Section, Source};
 
+/// Sequential reader a pack can be loaded from; see `PackSource` for the
+/// positional interface used for reads.
 pub trait PackReader: Read + Seek + Send {}
 impl<T: Read + Seek + Send> PackReader for T {}
 
+/// When `read_file` checks decoded data against `FileEntry.hash`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VerifyPolicy {
+    /// Trust the pack; never hash on read.
+    #[default]
+    Never,
+    /// Hash every read.
+    Always,
+    /// Hash the first successful read of each path, then trust it.
+    FirstRead,
+}
+
+/// How far the header, manifest and index of a pack can be trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthStatus {
+    /// Version 1/2 unencrypted pack with no auth record. Encrypted packs
+    /// older than version 3 are rejected instead.
+    Legacy,
+    /// Unkeyed SHA-256 matched: no accidental corruption, but not tamper-proof.
+    /// Only a valid publisher signature (`signature()`) shows an unencrypted
+    /// pack is unmodified.
+    Checksum,
+    /// Keyed HMAC matched the pack key.
+    Authenticated,
+    /// Keyed HMAC present but no key supplied yet; nothing can be read.
+    Pending,
+}
+
+/// Publisher signature found in the pack trailer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignatureInfo {
+    /// Ed25519 public key of the signer.
+    pub signer: [u8; 32],
+    /// Whether the signature matches the header, manifest and index.
+    pub valid: bool,
+}
+
+/// A pack opened for reading. Reads take `&self` and the type is `Sync`, so
+/// one pack can be shared (e.g. in an `Arc`) by several decoding threads.
 pub struct LoadedPack {
-    reader: Box<dyn PackReader>,
+    source: Box<dyn PackSource>,
     pub manifest: PackManifest,
-    entries: HashMap<String, FileEntry>,
-    _source_info: String,
+    state: PackState,
 }
 
 impl LoadedPack {
-    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+    /// Loads a pack using the default key source (the compile-time key when
+    /// the `embedded-key` feature is on, otherwise none).
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::load_with_key_provider(path, crypto::default_key_provider())
+    }
+
+    pub fn load_with_key<P: AsRef<Path>>(path: P, secret: &[u8]) -> Result<Self> {
+        Self::load_with_key_provider(path, Some(&crypto::StaticKey::new(secret)))
+    }
+
+    pub fn load_with_key_provider<P: AsRef<Path>>(path: P, keys: Option<&dyn KeyProvider>) -> Result<Self> {
         let path_str = path.as_ref().to_string_lossy().to_string();
         let file = File::open(path)?;
-        Self::load_from_reader(Box::new(file), path_str)
+        Self::load_from_source_with_key_provider(source::file_source(file), path_str, keys)
     }
 
-    pub fn load_from_memory(data: Vec<u8>) -> anyhow::Result<Self> {
-        let cursor = Cursor::new(data);
-        Self::load_from_reader(Box::new(cursor), "memory".to_string())
+    /// Maps the pack into memory instead of reading it through a file handle.
+    /// Entries decode straight from the mapped bytes, and `read_file_cow`
+    /// borrows `Store` entries without copying.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated while the pack is loaded;
+    /// see `MmapSource::new`.
+    #[cfg(feature = "mmap")]
+    pub unsafe fn load_mmap<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::load_mmap_with_key_provider(path, crypto::default_key_provider())
     }
 
-    pub fn load_from_reader(mut reader: Box<dyn PackReader>, source_info: String) -> anyhow::Result<Self> {
-        // 1. Check Magic
-        let mut magic = [0u8; 5];
-        reader.read_exact(&mut magic)?;
-        if &magic != b"LPACK" {
-            return Err(anyhow!("Invalid pack file format"));
-        }
+    /// # Safety
+    ///
+    /// Same as `load_mmap`.
+    #[cfg(feature = "mmap")]
+    pub unsafe fn load_mmap_with_key_provider<P: AsRef<Path>>(path: P, keys: Option<&dyn KeyProvider>) -> Result<Self> {
+        let path_str = path.as_ref().to_string_lossy().to_string();
+        let map = MmapSource::new(&File::open(path)?)?;
+        Self::load_from_source_with_key_provider(Box::new(map), path_str, keys)
+    }
+
+    pub fn load_from_memory(data: Vec<u8>) -> Result<Self> {
+        Self::load_from_memory_with_key_provider(data, crypto::default_key_provider())
+    }
+
+    pub fn load_from_memory_with_key_provider(data: Vec<u8>, keys: Option<&dyn KeyProvider>) -> Result<Self> {
+        Self::load_from_source_with_key_provider(Box::new(data), "memory".to_string(), keys)
+    }
 
-        // 2. Check Version
-        let mut version_bytes = [0u8; 4];
-        reader.read_exact(&mut version_bytes)?;
-        let _version = u32::from_le_bytes(version_bytes);
-
-        // 3. Read Manifest
-        let mut manifest_len_bytes = [0u8; 4];
-        reader.read_exact(&mut manifest_len_bytes)?;
-        let manifest_len = u32::from_le_bytes(manifest_len_bytes) as usize;
-        
-        let mut manifest_buf = vec![0u8; manifest_len];
-        reader.read_exact(&mut manifest_buf)?;
-        let manifest: PackManifest = serde_json::from_slice(&manifest_buf)?;
-
-        // 4. Read Index Pointers
-        let mut index_offset_bytes = [0u8; 8];
-        reader.read_exact(&mut index_offset_bytes)?;
-        let index_offset = u64::from_le_bytes(index_offset_bytes);
-
-        let mut index_len_bytes = [0u8; 4];
-        reader.read_exact(&mut index_len_bytes)?;
-        let index_len = u32::from_le_bytes(index_len_bytes) as usize;
-
-        // 5. Read Index
-        reader.seek(SeekFrom::Start(index_offset))?;
-        let mut index_buf = vec![0u8; index_len];
-        reader.read_exact(&mut index_buf)?;
-        let entry_list: Vec<FileEntry> = serde_json::from_slice(&index_buf)?;
-
-        let mut entries = HashMap::new();
-        for entry in entry_list {
-            entries.insert(entry.path.clone(), entry);
+    /// Loads from a `Read + Seek` reader. Reads from the pack are serialized
+    /// through a lock; implement `PackSource` for parallel reads.
+    pub fn load_from_reader(reader: Box<dyn PackReader>, source_info: String) -> Result<Self> {
+        Self::load_from_reader_with_key_provider(reader, source_info, crypto::default_key_provider())
+    }
+
+    pub fn load_from_reader_with_key_provider(
+        reader: Box<dyn PackReader>,
+        source_info: String,
+        keys: Option<&dyn KeyProvider>,
+    ) -> Result<Self> {
+        Self::load_from_source_with_key_provider(Box::new(LockedReader::new(reader)), source_info, keys)
+    }
+
+    pub fn load_from_source(source: Box<dyn PackSource>, source_info: String) -> Result<Self> {
+        Self::load_from_source_with_key_provider(source, source_info, crypto::default_key_provider())
+    }
+
+    pub fn load_from_source_with_key_provider(
+        source: Box<dyn PackSource>,
+        source_info: String,
+        keys: Option<&dyn KeyProvider>,
+    ) -> Result<Self> {
+        let source_len = source.size()?;
+        let mut prefix = [0u8; PREFIX_LEN];
+        source.read_exact_at(&mut prefix, 0)?;
+        let (version, rest_len) = state::parse_prefix(&prefix, source_len)?;
+
+        let mut rest = vec![0u8; rest_len];
+        source.read_exact_at(&mut rest, PREFIX_LEN as u64)?;
+        let header = Header::parse(version, rest, source_len)?;
+
+        let mut index_buf = vec![0u8; header.index_len];
+        source.read_exact_at(&mut index_buf, header.index_offset)?;
+        let mut trailer_buf = vec![0u8; header.trailer_len(source_len)?];
+        source.read_exact_at(&mut trailer_buf, header.index_end())?;
+
+        let (manifest, state) = PackState::new(header, &index_buf, &trailer_buf, source_info)?;
+        let mut pack = Self { source, manifest, state };
+        if let Some(keys) = keys {
+            pack.set_key_provider(keys)?;
         }
+        Ok(pack)
+    }
+
+    /// Sets the decryption secret, replacing any key found at load time. The
+    /// secret goes through the KDF recorded in the manifest (legacy packs
+    /// without one use plain SHA-256). On packs with a keyed tag the key must
+    /// authenticate the header, manifest and index, or it is rejected.
+    pub fn set_key(&mut self, secret: &[u8]) -> Result<()> {
+        self.state.set_key(secret)
+    }
+
+    pub fn auth_status(&self) -> AuthStatus {
+        self.state.auth_status
+    }
+
+    /// Publisher signature, if the pack is signed.
+    pub fn signature(&self) -> Option<SignatureInfo> {
+        self.state.signature
+    }
 
-        Ok(Self {
-            reader,
-            manifest,
-            entries,
-            _source_info: source_info,
-        })
+    /// Public key of the signer, only if the signature is valid.
+    pub fn signer(&self) -> Option<[u8; 32]> {
+        self.state.signature.filter(|s| s.valid).map(|s| s.signer)
     }
 
+    /// Asks `keys` for this pack's secret; keeps the current key if it has none.
+    pub fn set_key_provider(&mut self, keys: &dyn KeyProvider) -> Result<()> {
+        self.apply_key_provider(keys)
+    }
+
+    pub fn has_key(&self) -> bool {
+        self.state.has_key()
+    }
+
+    /// Header version of the loaded pack.
+    pub fn format_version(&self) -> u32 {
+        self.state.version
+    }
+
+    pub fn verify_policy(&self) -> VerifyPolicy {
+        self.state.verify_policy
+    }
+
+    pub fn set_verify_policy(&mut self, policy: VerifyPolicy) {
+        self.state.set_verify_policy(policy);
+    }
+
+    /// Also match paths that differ from the stored ones only in case, e.g.
+    /// `img/Pictures/Title.PNG` for `img/pictures/title.png`. An exact match
+    /// still wins; among stored paths differing only in case, the first in
+    /// sorted order is used. Lookups normalize separators and dot segments
+    /// either way.
+    pub fn set_case_insensitive(&mut self, enabled: bool) {
+        self.state.set_case_insensitive(enabled);
+    }
+
+    pub fn is_case_insensitive(&self) -> bool {
+        self.state.is_case_insensitive()
+    }
+
+    /// Looks up `path` after normalizing it (see `format::path`).
     pub fn get_entry(&self, path: &str) -> Option<&FileEntry> {
-        self.entries.get(path)
+        self.state.lookup(path)
     }
 
+    /// Paths with data, i.e. everything except tombstones, sorted.
     pub fn file_list(&self) -> Vec<String> {
-        self.entries.keys().cloned().collect()
+        self.files().map(|e| e.path.clone()).collect()
     }
 
-    pub fn read_file(&mut self, path: &str) -> anyhow::Result<Vec<u8>> {
-        let entry = self.entries.get(path).ok_or_else(|| anyhow!("File not found in pack"))?;
-        
-        self.reader.seek(SeekFrom::Start(entry.offset))?;
-        let mut raw_data = vec![0u8; entry.compressed_size as usize];
-        self.reader.read_exact(&mut raw_data)?;
-
-        // --- ระบบตรวจสอบและถอดรหัส (Backward Compatible) ---
-        let decrypted_data = match entry.encryption {
-            crate::format::EncryptionType::Aes256Gcm => {
-                if raw_data.len() < 12 {
-                    return Err(anyhow!("Invalid encrypted data structure"));
-                }
-                let (nonce_bytes, ciphertext) = raw_data.split_at(12);
-                let mut hasher = sha2::Sha256::new();
-                hasher.update(ENCRYPTION_KEY.as_bytes());
-                let key_hash = hasher.finalize();
-                
-                let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key_hash));
-                let nonce = Nonce::from_slice(nonce_bytes);
-
-                cipher.decrypt(nonce, ciphertext)
-                    .map_err(|_| anyhow!("Decryption failed! Invalid key or corrupted data."))?
-            },
-            crate::format::EncryptionType::None => {
-                // ถ้าไม่ได้เข้ารหัส (ไฟล์เก่า) ให้ใช้ข้อมูลดิบเลย
-                raw_data
-            }
-        };
+    /// File entries in path order, without tombstones.
+    pub fn files(&self) -> impl Iterator<Item = &FileEntry> {
+        self.state.files()
+    }
+
+    /// Immediate children of `dir` (`""` for the root): files, plus one
+    /// entry per subdirectory that has files under it. Sorted by path.
+    pub fn read_dir(&self, dir: &str) -> Vec<DirEntry> {
+        self.state.read_dir(dir)
+    }
+
+    /// Files matching a glob such as `**/*.png`, sorted. `*` stays within
+    /// one directory; `**` descends. The directories before the first
+    /// wildcard are normalized like paths, so `./img\*.png` works too.
+    pub fn glob(&self, pattern: &str) -> Result<Vec<String>> {
+        self.state.glob(pattern)
+    }
+
+    /// Whether `path` is a file or a directory with files under it.
+    pub fn exists(&self, path: &str) -> bool {
+        self.metadata(path).is_some()
+    }
+
+    pub fn metadata(&self, path: &str) -> Option<Metadata> {
+        self.state.metadata(path)
+    }
+
+    /// Paths this pack deletes from lower layers.
+    pub fn deleted_paths(&self) -> Vec<String> {
+        self.state.entries.values().filter(|e| e.is_tombstone()).map(|e| e.path.clone()).collect()
+    }
+
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        self.read_file_cow(path).map(Cow::into_owned)
+    }
+
+    /// Like `read_file`, but borrows unencrypted `Store` entries straight from
+    /// sources that live in memory (`load_mmap`, `load_from_memory`) instead
+    /// of copying them.
+    pub fn read_file_cow(&self, path: &str) -> Result<Cow<'_, [u8]>> {
+        let entry = self.state.readable_entry(path)?;
+        self.decode_file(&entry, self.state.should_verify(&entry))
+    }
 
-        match entry.compression {
-            CompressionType::Store => Ok(decrypted_data),
-            CompressionType::Zstd => {
-                let decoded = zstd::stream::decode_all(std::io::Cursor::new(decrypted_data))?;
-                Ok(decoded)
+    /// Reads and decodes a whole entry, checking its hash if `verify` is set.
+    fn decode_file(&self, entry: &FileEntry, verify: bool) -> Result<Cow<'_, [u8]>> {
+        let raw_data = self.read_raw(entry)?;
+        self.state.decode_entry(entry, raw_data, verify)
+    }
+
+    /// Opens an entry for streaming. Nothing is decoded until the reader is
+    /// read from, so large entries never have to fit in memory. Encrypted
+    /// `ZstdChunked` entries decrypt block by block as well; other encrypted
+    /// entries are sealed as one message and decrypted up front.
+    pub fn open_file(&self, path: &str) -> Result<EntryReader<'_>> {
+        let entry = self.state.readable_entry(path)?;
+        let verify = self.state.should_verify(&entry);
+        let source = match entry.encryption {
+            _ if entry.seals_blocks() => {
+                Source::Pack(Section::new(self.source.as_ref(), entry.offset, entry.compressed_size))
             },
-            CompressionType::Lz4 => {
-                let decoded = lz4_flex::decompress_size_prepended(&decrypted_data)
-                    .map_err(|e| anyhow!("LZ4 Decompression error: {}", e))?;
-                Ok(decoded)
+            EncryptionType::None => {
+                Source::Pack(Section::new(self.source.as_ref(), entry.offset, entry.compressed_size))
             },
+            EncryptionType::Aes256Gcm => {
+                let raw_data = self.read_raw(&entry)?;
+                Source::Memory(Cursor::new(self.state.decrypt_entry(&entry, raw_data)?.into_owned()))
+            },
+        };
+        let verified = (verify && self.state.verify_policy == VerifyPolicy::FirstRead)
+            .then_some(&self.state.verified);
+        EntryReader::new(&entry, source, self.state.decoder(), verify, verified)
+    }
+
+    /// Reads `len` bytes starting at `offset` of the decoded entry; the result
+    /// is shorter if the range runs past the end. `ZstdChunked` entries decode
+    /// only the blocks covering the range, `Store` entries read it directly,
+    /// and other compressions decode from the start. Range reads are not
+    /// checked against the entry hash.
+    pub fn read_range(&self, path: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let stored_len = self.state.readable_entry(path)?.compressed_size;
+        let mut reader = self.open_file(path)?;
+        reader.seek(SeekFrom::Start(offset))?;
+        let wanted = len.min(reader.len().saturating_sub(offset));
+        let mut data = Vec::with_capacity(chunked::prealloc_len(wanted, stored_len));
+        reader.take(len).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn read_raw(&self, entry: &FileEntry) -> Result<Cow<'_, [u8]>> {
+        if let Some(bytes) = self.source.as_slice() {
+            let raw_data = source::slice_at(bytes, entry.offset, entry.compressed_size as usize)
+                .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?;
+            return Ok(Cow::Borrowed(raw_data));
         }
+        let mut raw_data = vec![0u8; entry.compressed_size as usize];
+        self.source.read_exact_at(&mut raw_data, entry.offset)?;
+        Ok(Cow::Owned(raw_data))
+    }
+}
+
+impl Layer for LoadedPack {
+    fn manifest(&self) -> &PackManifest {
+        &self.manifest
+    }
+
+    fn state(&self) -> &PackState {
+        &self.state
+    }
+
+    fn state_mut(&mut self) -> &mut PackState {
+        &mut self.state
     }
 }
 
+#[derive(Default)]
 pub struct Resolver {
-    packs: Vec<LoadedPack>,
+    stack: LayerStack<LoadedPack>,
 }
 
 impl Resolver {
     pub fn new() -> Self {
-        Self { packs: Vec::new() }
+        Self::default()
+    }
+
+    pub fn with_key_provider(keys: Arc<dyn KeyProvider>) -> Self {
+        let mut resolver = Self::default();
+        resolver.stack.key_provider = Some(keys);
+        resolver
+    }
+
+    /// Restricts which packs `add_pack` accepts. Fails without changing
+    /// anything if a pack already in the resolver would be rejected.
+    /// Packs let in above `max_unsigned_priority` are read with at least
+    /// `VerifyPolicy::FirstRead`: the signature vouches for the file hashes,
+    /// and checking them is what ties the data to it.
+    pub fn set_trust_policy(&mut self, policy: TrustPolicy) -> Result<()> {
+        self.stack.set_trust_policy(policy)
+    }
+
+    /// Sets the provider used by `load_pack` and for packs added without a key.
+    pub fn set_key_provider(&mut self, keys: Arc<dyn KeyProvider>) -> Result<()> {
+        self.stack.set_key_provider(keys)
+    }
+
+    /// Loads a pack from disk with this resolver's key provider and adds it.
+    pub fn load_pack<P: AsRef<Path>>(&mut self, path: P) -> Result<PackHandle> {
+        let pack = match &self.stack.key_provider {
+            Some(keys) => LoadedPack::load_with_key_provider(path, Some(keys.as_ref()))?,
+            None => LoadedPack::load(path)?,
+        };
+        self.add_pack(pack)
+    }
+
+    /// Adds a pack to the layer stack. Fails if the trust policy rejects it.
+    /// The handle can later remove, reorder or disable the pack.
+    ///
+    /// Higher priorities win. Packs with equal priority resolve in the order
+    /// they were added, earliest first; `priority_conflicts` lists the paths
+    /// where that tie-break decided the winner.
+    pub fn add_pack(&mut self, pack: LoadedPack) -> Result<PackHandle> {
+        self.stack.add(pack)
+    }
+
+    /// Unloads a pack and hands it back, e.g. when a player uninstalls a mod.
+    pub fn remove_pack(&mut self, handle: PackHandle) -> Option<LoadedPack> {
+        self.stack.remove(handle)
+    }
+
+    /// Moves a pack to a new priority, overriding its manifest. Fails without
+    /// changing anything if the trust policy rejects the pack at that
+    /// priority; returns false if the handle is unknown.
+    pub fn set_priority(&mut self, handle: PackHandle, priority: i32) -> Result<bool> {
+        self.stack.set_priority(handle, priority)
+    }
+
+    /// Disabled packs stay loaded but are skipped by `resolve` and
+    /// `list_layers`. Returns false if the handle is unknown.
+    pub fn set_enabled(&mut self, handle: PackHandle, enabled: bool) -> bool {
+        self.stack.set_enabled(handle, enabled)
+    }
+
+    pub fn pack(&self, handle: PackHandle) -> Option<&LoadedPack> {
+        self.stack.get(handle)
+    }
+
+    /// Turns case-insensitive lookup on or off for every pack, including
+    /// ones added later, replacing each pack's own setting. Applies to
+    /// `resolve`, listings and globs alike; see
+    /// `LoadedPack::set_case_insensitive`.
+    pub fn set_case_insensitive(&mut self, enabled: bool) {
+        self.stack.set_case_insensitive(enabled);
+    }
+
+    /// Picks packs by `PackManifest.lang`. `th-TH` gives the chain
+    /// `th-TH -> th -> en`: packs whose language is not in the chain are
+    /// skipped, and packs without a language are always used. At equal
+    /// priority, a pack earlier in the chain wins, and packs without a
+    /// language come last. Tags compare case-insensitively, with `_` and `-`
+    /// treated alike. An empty or blank `locale` turns filtering off.
+    pub fn set_locale(&mut self, locale: &str) {
+        self.stack.set_locale_chain(stack::locale_chain(locale));
+    }
+
+    /// Like `set_locale`, with an explicit chain (most preferred first) and
+    /// no implicit English fallback. An empty chain turns filtering off.
+    pub fn set_locale_chain(&mut self, chain: Vec<String>) {
+        self.stack.set_locale_chain(chain);
+    }
+
+    /// The active chain as normalized tags; empty when no locale is set.
+    pub fn locale(&self) -> &[String] {
+        self.stack.locale()
+    }
+
+    /// Every path under `dir` (recursively, `""` for all) that `resolve`
+    /// would serve, sorted, with the pack serving it. Overrides, disabled
+    /// packs, the locale and tombstones apply as they do for `resolve`.
+    pub fn list_dir(&self, dir: &str) -> Vec<ResolvedEntry> {
+        self.stack.list_dir(dir)
+    }
+
+    /// Like `list_dir`, for paths matching a glob such as `maps/*.json`.
+    /// `*` stays within one directory; use `**` to descend, as in
+    /// `lang/**/*.txt`. Leading `./` and `/` and `\` separators before the
+    /// first wildcard are normalized as in `list_dir`.
+    pub fn glob(&self, pattern: &str) -> Result<Vec<ResolvedEntry>> {
+        self.stack.glob(pattern)
+    }
+
+    /// Paths that two or more enabled packs provide at the same winning
+    /// priority and language, sorted by path. Worth logging at startup: the winner then
+    /// depends on load order rather than on the packs themselves.
+    pub fn priority_conflicts(&self) -> Vec<PriorityConflict> {
+        self.stack.priority_conflicts()
+    }
+
+    /// Reads `path` from the highest layer that has it. Merge patch entries
+    /// above that layer are applied to it in priority order, lowest first,
+    /// and the result is returned as compact JSON. Returns `None` if the
+    /// file is missing or deleted, and also if it cannot be read or patched;
+    /// use `try_resolve` to tell those apart.
+    pub fn resolve(&self, path: &str) -> Option<Vec<u8>> {
+        self.try_resolve(path).ok().flatten()
+    }
+
+    /// Like `resolve`, but read and patch failures (e.g. `PatchFailed` for a
+    /// base file that is not JSON) are returned instead of becoming `None`.
+    pub fn try_resolve(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.try_resolve_shared(path)?.map(|data| data.to_vec()))
     }
 
-    pub fn add_pack(&mut self, pack: LoadedPack) {
-        self.packs.push(pack);
-        // Sort by priority (descending)
-        self.packs.sort_by(|a, b| b.manifest.priority.cmp(&a.manifest.priority));
+    /// Like `resolve`, but hands out the cached buffer itself instead of a
+    /// copy when the cache is on.
+    pub fn resolve_shared(&self, path: &str) -> Option<Arc<[u8]>> {
+        self.try_resolve_shared(path).ok().flatten()
     }
 
-    pub fn resolve(&mut self, path: &str) -> Option<Vec<u8>> {
-        for pack in &mut self.packs {
-            if pack.entries.contains_key(path) {
-                 return pack.read_file(path).ok();
-            }
+    /// `try_resolve` without the copy; see `resolve_shared`.
+    pub fn try_resolve_shared(&self, path: &str) -> Result<Option<Arc<[u8]>>> {
+        let Some(resolution) = self.stack.resolution(path) else {
+            return Ok(None);
+        };
+        // Cache under the stored path, however the caller spelled it
+        let (top, entry) = resolution.top();
+        if let Some(data) = self.stack.cached(top.id, &entry.path) {
+            return Ok(Some(data));
         }
-        None
+        let (base, base_entry) = resolution.base;
+        let base_data = base.pack.read_file_cow(&base_entry.path)?;
+        let data: Arc<[u8]> = if resolution.patches.is_empty() {
+            base_data.into()
+        } else {
+            let patches = resolution.patches.iter().rev()
+                .map(|(slot, patch)| slot.pack.read_file_cow(&patch.path))
+                .collect::<Result<Vec<_>>>()?;
+            patch::compose(&entry.path, &base_data, patches.iter().map(|p| p.as_ref()))?.into()
+        };
+        self.stack.store(top.id, &entry.path, data.clone());
+        Ok(Some(data))
+    }
+
+    /// Keeps up to `max_bytes` of decoded assets so repeated `resolve` calls
+    /// skip decryption and decompression. Entries are keyed by the winning
+    /// pack and path, evicted least recently used first, and dropped whenever
+    /// the layer stack changes. 0 turns the cache off (the default).
+    pub fn set_cache_capacity(&mut self, max_bytes: usize) {
+        self.stack.set_cache_capacity(max_bytes);
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.stack.cache_stats()
+    }
+
+    /// Drops `path` from the cache; returns whether it was cached.
+    pub fn evict(&self, path: &str) -> bool {
+        self.stack.evict(path)
+    }
+
+    pub fn clear_cache(&self) {
+        self.stack.clear_cache();
     }
     
     pub fn list_layers(&self, path: &str) -> Vec<String> {
-        let mut found_in = Vec::new();
-        for pack in &self.packs {
-            if pack.entries.contains_key(path) {
-                found_in.push(format!("{} (Priority: {})", pack.manifest.name, pack.manifest.priority));
-            }
-        }
-        found_in
+        self.stack.list_layers(path)
     }
 }
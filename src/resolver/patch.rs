@@ -0,0 +1,89 @@
+//! JSON Merge Patch (RFC 7396) layers, composed at resolve time.
+
+use serde_json::{Map, Value};
+use crate::error::{LayerPackError, Result};
+
+/// Applies `patch` to `target` in place: objects merge key by key, `null`
+/// removes a key, and anything else replaces the target. Key order of the
+/// target is kept, with new keys appended.
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let Value::Object(target) = target else { return };
+    for (key, value) in patch {
+        if value.is_null() {
+            target.shift_remove(key);
+        } else {
+            merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// Parses `base` and applies `patches` lowest layer first.
+pub(crate) fn compose<'a>(path: &str, base: &[u8], patches: impl IntoIterator<Item = &'a [u8]>) -> Result<Vec<u8>> {
+    let failed = |reason: String| LayerPackError::PatchFailed { path: path.to_string(), reason };
+    let mut value: Value = serde_json::from_slice(base)
+        .map_err(|e| failed(format!("base file is not valid JSON: {}", e)))?;
+    for patch in patches {
+        let patch: Value = serde_json::from_slice(patch)
+            .map_err(|e| failed(format!("patch is not valid JSON: {}", e)))?;
+        merge_patch(&mut value, &patch);
+    }
+    serde_json::to_vec(&value).map_err(|e| failed(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn rfc_7396_appendix_a() {
+        let cases = [
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "b"}), json!({"b": "c"}), json!({"a": "b", "b": "c"})),
+            (json!({"a": "b"}), json!({"a": null}), json!({})),
+            (json!({"a": "b", "b": "c"}), json!({"a": null}), json!({"b": "c"})),
+            (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+            (json!({"a": {"b": "c"}}), json!({"a": {"b": "d", "c": null}}), json!({"a": {"b": "d"}})),
+            (json!({"a": [{"b": "c"}]}), json!({"a": [1]}), json!({"a": [1]})),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a": "b"}), json!(["c"]), json!(["c"])),
+            (json!({"a": "foo"}), json!(null), json!(null)),
+            (json!({"a": "foo"}), json!("bar"), json!("bar")),
+            (json!({"e": null}), json!({"a": 1}), json!({"e": null, "a": 1})),
+            (json!([1, 2]), json!({"a": "b", "c": null}), json!({"a": "b"})),
+            (json!({}), json!({"a": {"bb": {"ccc": null}}}), json!({"a": {"bb": {}}})),
+        ];
+        for (mut target, patch, expected) in cases {
+            let original = target.clone();
+            merge_patch(&mut target, &patch);
+            assert_eq!(target, expected, "{original} + {patch}");
+        }
+    }
+
+    #[test]
+    fn compose_applies_patches_lowest_first_and_keeps_key_order() {
+        let base = br#"{"name":"Harold","params":{"hp":100,"atk":5},"note":""}"#;
+        let patches: [&[u8]; 2] = [br#"{"params":{"atk":7},"name":"Therese"}"#, br#"{"name":"Harold the Brave","note":null}"#];
+        let composed = compose("data/Actors.json", base, patches).unwrap();
+        assert_eq!(composed, br#"{"name":"Harold the Brave","params":{"hp":100,"atk":7}}"#);
+    }
+
+    #[test]
+    fn compose_reports_invalid_json() {
+        let patch: &[u8] = br#"{"a":1}"#;
+        for (base, patch) in [(&b"not json"[..], patch), (&br#"{"a":0}"#[..], &b"{"[..])] {
+            match compose("data/System.json", base, [patch]) {
+                Err(LayerPackError::PatchFailed { path, .. }) => assert_eq!(path, "data/System.json"),
+                other => panic!("expected PatchFailed, got {other:?}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use super::PackReader;
+
+/// Random-access bytes a pack is read from. Reads are positional and take
+/// `&self`, so one `LoadedPack` can serve many threads without a lock.
+pub trait PackSource: Send + Sync {
+    /// Fills `buf` with the bytes starting at `offset`, failing if the source
+    /// ends first.
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+
+    /// Total size of the source in bytes.
+    fn size(&self) -> io::Result<u64>;
+
+    /// The whole source as one slice, for sources already in memory. When
+    /// present, entries are decoded straight from it and `Store` entries are
+    /// borrowed instead of copied.
+    fn as_slice(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+#[cfg(unix)]
+impl PackSource for File {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        std::os::unix::fs::FileExt::read_exact_at(self, buf, offset)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+#[cfg(windows)]
+impl PackSource for File {
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        use std::os::windows::fs::FileExt;
+        // seek_read moves the shared cursor, but never reads from it
+        while !buf.is_empty() {
+            match self.seek_read(buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+impl PackSource for Vec<u8> {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let bytes = slice_at(self, offset, buf.len()).ok_or(io::ErrorKind::UnexpectedEof)?;
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn as_slice(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+}
+
+/// A pack file mapped into memory.
+#[cfg(feature = "mmap")]
+pub struct MmapSource(memmap2::Mmap);
+
+#[cfg(feature = "mmap")]
+impl MmapSource {
+    /// Maps `file` read-only.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated while the map is alive;
+    /// another process changing it is undefined behaviour.
+    pub unsafe fn new(file: &File) -> io::Result<Self> {
+        Ok(Self(memmap2::Mmap::map(file)?))
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl PackSource for MmapSource {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let bytes = slice_at(&self.0, offset, buf.len()).ok_or(io::ErrorKind::UnexpectedEof)?;
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.0.len() as u64)
+    }
+
+    fn as_slice(&self) -> Option<&[u8]> {
+        Some(&self.0)
+    }
+}
+
+/// `len` bytes of `bytes` starting at `offset`, if they are all there.
+pub(crate) fn slice_at(bytes: &[u8], offset: u64, len: usize) -> Option<&[u8]> {
+    let start = usize::try_from(offset).ok()?;
+    bytes.get(start..start.checked_add(len)?)
+}
+
+/// Adapts a `Read + Seek` reader that has no positional reads. Every read
+/// takes the lock, so threads sharing the pack take turns.
+pub struct LockedReader(Mutex<Box<dyn PackReader>>);
+
+impl LockedReader {
+    pub fn new(reader: Box<dyn PackReader>) -> Self {
+        Self(Mutex::new(reader))
+    }
+}
+
+impl PackSource for LockedReader {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let mut reader = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(buf)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        let mut reader = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        reader.seek(SeekFrom::End(0))
+    }
+}
+
+/// Opens `file` as the best source the platform offers.
+#[cfg(any(unix, windows))]
+pub(crate) fn file_source(file: File) -> Box<dyn PackSource> {
+    Box::new(file)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn file_source(file: File) -> Box<dyn PackSource> {
+    Box::new(LockedReader::new(Box::new(file)))
+}
@@ -0,0 +1,436 @@
+//! Layer ordering shared by `Resolver` and `AsyncResolver`: which packs are
+//! accepted, which keys they get, and which pack wins for a path. Only the
+//! final read differs between the two.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use crate::crypto::KeyProvider;
+use crate::error::Result;
+use crate::format::{EntryKind, FileEntry, PackManifest};
+use crate::format::path as pack_path;
+use super::cache::{AssetCache, CacheStats};
+use super::listing::{self, ResolvedEntry};
+use super::state::PackState;
+use super::TrustPolicy;
+
+/// A pack the layer stack can hold.
+pub(crate) trait Layer {
+    fn manifest(&self) -> &PackManifest;
+    fn state(&self) -> &PackState;
+    fn state_mut(&mut self) -> &mut PackState;
+
+    /// Asks `keys` for this pack's secret; keeps the current key if it has none.
+    fn apply_key_provider(&mut self, keys: &dyn KeyProvider) -> Result<()> {
+        if let Some(secret) = keys.key_for(self.manifest()) {
+            self.state_mut().set_key(&secret)?;
+        }
+        Ok(())
+    }
+
+    fn check_trust(&self, policy: &TrustPolicy, priority: i32) -> Result<()> {
+        policy.check_signature(&self.manifest().name, self.state().signature, priority)
+    }
+
+    /// Called once `check_trust` passed. The signature covers the index but
+    /// not the data it points at, so a pack let in on its signature has its
+    /// entry hashes checked on read as well.
+    fn admitted(&mut self, policy: &TrustPolicy, priority: i32) {
+        if priority > policy.max_unsigned_priority() {
+            self.state_mut().require_verification();
+        }
+    }
+}
+
+/// Identifies a pack added to a `Resolver`, for removing or reordering it
+/// later. Handles are never reused, so a stale one simply matches nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PackHandle(u64);
+
+/// A path served by more than one enabled pack at the same, highest priority
+/// and equally preferred language. The first pack in `packs` wins: the pack
+/// added earlier takes precedence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PriorityConflict {
+    pub path: String,
+    pub priority: i32,
+    /// Tied packs in resolution order, winner first.
+    pub packs: Vec<PackHandle>,
+}
+
+/// A pack in the stack, with its place in the layering.
+pub(crate) struct Slot<P> {
+    pub id: u64,
+    pub pack: P,
+    /// Starts as the manifest priority; `set_priority` overrides it.
+    pub priority: i32,
+    pub enabled: bool,
+    /// Position of the pack's language in the locale chain; `None` when the
+    /// locale excludes the pack.
+    pub lang_rank: Option<usize>,
+}
+
+/// The file serving a path and the merge patches stacked on it, each with
+/// the pack it comes from.
+pub(crate) struct Resolution<'a, P> {
+    pub base: (&'a Slot<P>, &'a FileEntry),
+    /// Highest layer first.
+    pub patches: Vec<(&'a Slot<P>, &'a FileEntry)>,
+}
+
+impl<'a, P> Resolution<'a, P> {
+    /// The highest layer involved; it identifies the result in the cache.
+    pub fn top(&self) -> (&'a Slot<P>, &'a FileEntry) {
+        self.patches.first().copied().unwrap_or(self.base)
+    }
+}
+
+pub(crate) struct LayerStack<P> {
+    /// Highest priority first.
+    pub packs: Vec<Slot<P>>,
+    next_id: u64,
+    pub key_provider: Option<Arc<dyn KeyProvider>>,
+    pub trust_policy: Option<TrustPolicy>,
+    cache: Option<Mutex<AssetCache>>,
+    /// Normalized language tags, most preferred first. Empty means every
+    /// pack is used regardless of language.
+    locale: Vec<String>,
+    /// Applied to every pack, so all index keys in the stack agree.
+    case_insensitive: bool,
+    /// Built on the first listing after the stack changes.
+    merged: OnceLock<BTreeMap<String, ResolvedEntry>>,
+}
+
+impl<P> Default for LayerStack<P> {
+    fn default() -> Self {
+        Self {
+            packs: Vec::new(),
+            next_id: 0,
+            key_provider: None,
+            trust_policy: None,
+            cache: None,
+            locale: Vec::new(),
+            case_insensitive: false,
+            merged: OnceLock::new(),
+        }
+    }
+}
+
+/// Lowercases a language tag and accepts `_` as a subtag separator, so
+/// `th_TH` and `th-th` match `th-TH`.
+fn normalize_lang(tag: &str) -> String {
+    tag.trim().replace('_', "-").to_ascii_lowercase()
+}
+
+/// `th-TH` -> `[th-th, th, en]`: the tag, each shorter prefix, then English.
+/// A blank locale gives an empty chain, which turns filtering off.
+pub(crate) fn locale_chain(locale: &str) -> Vec<String> {
+    let tag = normalize_lang(locale);
+    if tag.is_empty() {
+        return Vec::new();
+    }
+    let mut chain = Vec::new();
+    let mut rest = tag.as_str();
+    while !rest.is_empty() {
+        chain.push(rest.to_string());
+        rest = rest.rfind('-').map_or("", |i| &rest[..i]);
+    }
+    if !chain.iter().any(|t| t == "en") {
+        chain.push("en".to_string());
+    }
+    chain
+}
+
+impl<P> LayerStack<P> {
+    /// Replaces the cache with an empty one holding up to `max_bytes`; 0 turns
+    /// caching off.
+    pub fn set_cache_capacity(&mut self, max_bytes: usize) {
+        self.cache = (max_bytes > 0).then(|| Mutex::new(AssetCache::new(max_bytes)));
+    }
+
+    fn cache(&self) -> Option<MutexGuard<'_, AssetCache>> {
+        self.cache.as_ref().map(|cache| cache.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache().map(|cache| cache.stats()).unwrap_or_default()
+    }
+
+    pub fn cached(&self, id: u64, path: &str) -> Option<Arc<[u8]>> {
+        self.cache()?.get(id, path)
+    }
+
+    pub fn store(&self, id: u64, path: &str, data: Arc<[u8]>) {
+        if let Some(mut cache) = self.cache() {
+            cache.insert(id, path, data);
+        }
+    }
+
+    pub fn clear_cache(&self) {
+        if let Some(mut cache) = self.cache() {
+            cache.clear();
+        }
+    }
+}
+
+impl<P: Layer> LayerStack<P> {
+    pub fn set_trust_policy(&mut self, policy: TrustPolicy) -> Result<()> {
+        for slot in &self.packs {
+            slot.pack.check_trust(&policy, slot.priority)?;
+        }
+        for slot in &mut self.packs {
+            slot.pack.admitted(&policy, slot.priority);
+        }
+        self.trust_policy = Some(policy);
+        // Anything cached so far may not have been hash-checked
+        self.clear_cache();
+        Ok(())
+    }
+
+    pub fn set_key_provider(&mut self, keys: Arc<dyn KeyProvider>) -> Result<()> {
+        for slot in &mut self.packs {
+            if !slot.pack.state().has_key() {
+                slot.pack.apply_key_provider(keys.as_ref())?;
+            }
+        }
+        self.key_provider = Some(keys);
+        Ok(())
+    }
+
+    pub fn add(&mut self, mut pack: P) -> Result<PackHandle> {
+        let priority = pack.manifest().priority;
+        if let Some(policy) = &self.trust_policy {
+            pack.check_trust(policy, priority)?;
+            pack.admitted(policy, priority);
+        }
+        if let Some(keys) = self.key_provider.as_ref().filter(|_| !pack.state().has_key()) {
+            pack.apply_key_provider(keys.as_ref())?;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        pack.state_mut().set_case_insensitive(self.case_insensitive);
+        let lang_rank = self.lang_rank(pack.manifest());
+        self.packs.push(Slot { id, pack, priority, enabled: true, lang_rank });
+        self.reorder();
+        Ok(PackHandle(id))
+    }
+
+    pub fn remove(&mut self, handle: PackHandle) -> Option<P> {
+        let index = self.packs.iter().position(|slot| slot.id == handle.0)?;
+        let slot = self.packs.remove(index);
+        self.changed();
+        Some(slot.pack)
+    }
+
+    /// Moves a pack to `priority`, re-checking it against the trust policy
+    /// first. Returns false for an unknown handle.
+    pub fn set_priority(&mut self, handle: PackHandle, priority: i32) -> Result<bool> {
+        let Some(slot) = self.packs.iter_mut().find(|slot| slot.id == handle.0) else {
+            return Ok(false);
+        };
+        if let Some(policy) = &self.trust_policy {
+            slot.pack.check_trust(policy, priority)?;
+            slot.pack.admitted(policy, priority);
+        }
+        slot.priority = priority;
+        self.reorder();
+        Ok(true)
+    }
+
+    /// Returns false for an unknown handle.
+    pub fn set_enabled(&mut self, handle: PackHandle, enabled: bool) -> bool {
+        let Some(slot) = self.packs.iter_mut().find(|slot| slot.id == handle.0) else {
+            return false;
+        };
+        if slot.enabled != enabled {
+            slot.enabled = enabled;
+            self.changed();
+        }
+        true
+    }
+
+    pub fn get(&self, handle: PackHandle) -> Option<&P> {
+        self.packs.iter().find(|slot| slot.id == handle.0).map(|slot| &slot.pack)
+    }
+
+    /// Evicts the stored path `path` resolves to, so any spelling works.
+    pub fn evict(&self, path: &str) -> bool {
+        let key = match self.resolution(path) {
+            Some(resolution) => resolution.top().1.path.clone(),
+            None => pack_path::normalize(path).into_owned(),
+        };
+        self.cache().is_some_and(|mut cache| cache.remove(&key))
+    }
+
+    pub fn set_case_insensitive(&mut self, enabled: bool) {
+        self.case_insensitive = enabled;
+        for slot in &mut self.packs {
+            slot.pack.state_mut().set_case_insensitive(enabled);
+        }
+        self.changed();
+    }
+
+    pub fn locale(&self) -> &[String] {
+        &self.locale
+    }
+
+    pub fn set_locale_chain(&mut self, chain: Vec<String>) {
+        self.locale = chain.iter().map(|tag| normalize_lang(tag)).collect();
+        for i in 0..self.packs.len() {
+            self.packs[i].lang_rank = self.lang_rank(self.packs[i].pack.manifest());
+        }
+        self.reorder();
+    }
+
+    /// Packs without a language come after every tag in the chain.
+    fn lang_rank(&self, manifest: &PackManifest) -> Option<usize> {
+        if self.locale.is_empty() {
+            return Some(0);
+        }
+        match &manifest.lang {
+            None => Some(self.locale.len()),
+            Some(lang) => {
+                let lang = normalize_lang(lang);
+                self.locale.iter().position(|tag| *tag == lang)
+            }
+        }
+    }
+
+    fn reorder(&mut self) {
+        // Highest priority first; on a tie the better language match wins,
+        // then the pack added first. IDs only grow, so this ignores how
+        // earlier sorts left equal packs.
+        self.packs.sort_by_key(|slot| (std::cmp::Reverse(slot.priority), slot.lang_rank, slot.id));
+        self.changed();
+    }
+
+    /// Drops everything derived from which pack wins a path.
+    fn changed(&mut self) {
+        // Cached entries from packs that no longer win would only miss
+        self.clear_cache();
+        self.merged = OnceLock::new();
+    }
+
+    fn active(&self) -> impl Iterator<Item = &Slot<P>> {
+        self.packs.iter().filter(|slot| slot.enabled && slot.lang_rank.is_some())
+    }
+
+    /// How `path` resolves across the enabled packs: merge patches collect
+    /// from the top down until a file ends the search. A tombstone first, or
+    /// no file at all, means the path is missing.
+    pub fn resolution(&self, path: &str) -> Option<Resolution<'_, P>> {
+        let mut patches = Vec::new();
+        for slot in self.active() {
+            let Some(entry) = slot.pack.state().lookup(path) else { continue };
+            match entry.kind {
+                EntryKind::MergePatch => patches.push((slot, entry)),
+                EntryKind::Tombstone => return None,
+                EntryKind::File => return Some(Resolution { base: (slot, entry), patches }),
+            }
+        }
+        None
+    }
+
+    /// Index key -> winning path and pack, for every visible path.
+    fn merged(&self) -> &BTreeMap<String, ResolvedEntry> {
+        enum Seen {
+            Visible(ResolvedEntry),
+            Hidden,
+            /// Only patches so far; visible once a file turns up below.
+            Patched(ResolvedEntry),
+        }
+        self.merged.get_or_init(|| {
+            let mut seen: BTreeMap<&str, Seen> = BTreeMap::new();
+            for slot in self.active() {
+                for (key, entry) in slot.pack.state().index_under("") {
+                    let resolved = || ResolvedEntry { path: entry.path.clone(), pack: PackHandle(slot.id) };
+                    let state = match (seen.remove(key), entry.kind) {
+                        (None, EntryKind::File) => Seen::Visible(resolved()),
+                        (None, EntryKind::Tombstone) => Seen::Hidden,
+                        (None, EntryKind::MergePatch) => Seen::Patched(resolved()),
+                        (Some(Seen::Patched(top)), EntryKind::File) => Seen::Visible(top),
+                        (Some(Seen::Patched(_)), EntryKind::Tombstone) => Seen::Hidden,
+                        (Some(state), _) => state,
+                    };
+                    seen.insert(key, state);
+                }
+            }
+            seen.into_iter()
+                .filter_map(|(key, state)| match state {
+                    Seen::Visible(resolved) => Some((key.to_string(), resolved)),
+                    Seen::Hidden | Seen::Patched(_) => None,
+                })
+                .collect()
+        })
+    }
+
+    pub fn list_dir(&self, dir: &str) -> Vec<ResolvedEntry> {
+        let prefix = listing::index_key(listing::dir_prefix(dir), self.case_insensitive);
+        listing::with_prefix(self.merged(), &prefix).map(|(_, resolved)| resolved.clone()).collect()
+    }
+
+    pub fn glob(&self, pattern: &str) -> Result<Vec<ResolvedEntry>> {
+        let pattern = &listing::normalize_glob(pattern);
+        let matcher = listing::compile_glob(pattern, self.case_insensitive)?;
+        let prefix = listing::index_key(listing::glob_prefix(pattern).to_string(), self.case_insensitive);
+        Ok(listing::with_prefix(self.merged(), &prefix)
+            .filter(|(_, resolved)| matcher.is_match(&resolved.path))
+            .map(|(_, resolved)| resolved.clone())
+            .collect())
+    }
+
+    pub fn priority_conflicts(&self) -> Vec<PriorityConflict> {
+        let mut by_key: BTreeMap<&str, Vec<(&Slot<P>, &FileEntry)>> = BTreeMap::new();
+        for slot in self.active() {
+            // Patches compose rather than compete
+            for (key, entry) in slot.pack.state().index_under("").filter(|(_, e)| !e.is_merge_patch()) {
+                by_key.entry(key).or_default().push((slot, entry));
+            }
+        }
+        by_key
+            .into_values()
+            .filter_map(|layers| {
+                let (first, entry) = layers[0];
+                let packs: Vec<PackHandle> = layers
+                    .iter()
+                    .take_while(|(slot, _)| slot.priority == first.priority && slot.lang_rank == first.lang_rank)
+                    .map(|(slot, _)| PackHandle(slot.id))
+                    .collect();
+                (packs.len() > 1).then(|| PriorityConflict { path: entry.path.clone(), priority: first.priority, packs })
+            })
+            .collect()
+    }
+
+    pub fn list_layers(&self, path: &str) -> Vec<String> {
+        let mut found_in = Vec::new();
+        for slot in self.active() {
+            if let Some(entry) = slot.pack.state().lookup(path) {
+                let kind = match entry.kind {
+                    EntryKind::File => "",
+                    EntryKind::Tombstone => ", deleted",
+                    EntryKind::MergePatch => ", patch",
+                };
+                found_in.push(format!("{} (Priority: {}{})", slot.pack.manifest().name, slot.priority, kind));
+            }
+        }
+        found_in
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locale_chain_falls_back_through_prefixes_to_english() {
+        assert_eq!(locale_chain("th_TH"), ["th-th", "th", "en"]);
+        assert_eq!(locale_chain("zh-Hant-TW"), ["zh-hant-tw", "zh-hant", "zh", "en"]);
+        assert_eq!(locale_chain(" EN-us "), ["en-us", "en"]);
+    }
+
+    #[test]
+    fn blank_locale_gives_an_empty_chain() {
+        for locale in ["", "   ", "\t\n"] {
+            assert!(locale_chain(locale).is_empty(), "{locale:?}");
+        }
+    }
+}
@@ -0,0 +1,598 @@
+//! Everything about a loaded pack except where its bytes come from. Both
+//! `LoadedPack` and the async reader parse headers and decode entries through
+//! here, so the two only differ in how they fetch byte ranges.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::io::Read;
+use std::sync::{Mutex, MutexGuard};
+use sha2::Digest;
+use crate::crypto;
+use crate::error::{LayerPackError, Result};
+use crate::format::{
+    PackManifest, FileEntry, CompressionType, EncryptionType, Kdf, MAGIC,
+    FORMAT_VERSION_JSON_INDEX, FORMAT_VERSION_BINARY_INDEX, FORMAT_VERSION_TRAILER, FORMAT_VERSION_ENTRY_AAD,
+};
+use crate::format::chunked;
+use crate::format::path as pack_path;
+use crate::format::index::decode_index;
+use crate::format::trailer::{auth_digest, decode_trailer, AuthTag, Trailer, MAX_TRAILER_LEN};
+use super::{AuthStatus, SignatureInfo, VerifyPolicy};
+
+/// Magic, version and manifest length.
+pub(crate) const PREFIX_LEN: usize = 13;
+
+/// Index offset and length, right after the manifest.
+const POINTERS_LEN: u64 = 12;
+
+/// Where each region of the pack sits in the source, recorded at load time.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PackLayout {
+    pub data_start: u64,
+    pub index_offset: u64,
+}
+
+/// Checks the magic and version; returns the version and how many bytes of
+/// manifest and index pointers follow the prefix.
+pub(crate) fn parse_prefix(prefix: &[u8; PREFIX_LEN], source_len: u64) -> Result<(u32, usize)> {
+    // 1. Check Magic
+    if &prefix[..5] != MAGIC {
+        return Err(LayerPackError::BadMagic);
+    }
+
+    // 2. Check Version
+    let version = u32::from_le_bytes([prefix[5], prefix[6], prefix[7], prefix[8]]);
+    if !matches!(
+        version,
+        FORMAT_VERSION_JSON_INDEX | FORMAT_VERSION_BINARY_INDEX | FORMAT_VERSION_TRAILER | FORMAT_VERSION_ENTRY_AAD
+    ) {
+        return Err(LayerPackError::unsupported_version(version));
+    }
+
+    let manifest_len = u32::from_le_bytes([prefix[9], prefix[10], prefix[11], prefix[12]]) as u64;
+    if PREFIX_LEN as u64 + manifest_len + POINTERS_LEN > source_len {
+        return Err(LayerPackError::CorruptManifest("manifest runs past the end of the pack".to_string()));
+    }
+    Ok((version, (manifest_len + POINTERS_LEN) as usize))
+}
+
+/// Manifest and index pointers, parsed from the bytes after the prefix.
+pub(crate) struct Header {
+    version: u32,
+    manifest_buf: Vec<u8>,
+    manifest: PackManifest,
+    pub index_offset: u64,
+    pub index_len: usize,
+    data_start: u64,
+}
+
+impl Header {
+    pub(crate) fn parse(version: u32, mut rest: Vec<u8>, source_len: u64) -> Result<Self> {
+        // 3. Read Manifest
+        let pointers = rest.split_off(rest.len() - POINTERS_LEN as usize);
+        let manifest_buf = rest;
+        let manifest: PackManifest = serde_json::from_slice(&manifest_buf)
+            .map_err(|e| LayerPackError::CorruptManifest(e.to_string()))?;
+
+        // 4. Read Index Pointers
+        let mut index_offset_bytes = [0u8; 8];
+        index_offset_bytes.copy_from_slice(&pointers[..8]);
+        let index_offset = u64::from_le_bytes(index_offset_bytes);
+        let index_len = u32::from_le_bytes([pointers[8], pointers[9], pointers[10], pointers[11]]) as usize;
+        let data_start = PREFIX_LEN as u64 + manifest_buf.len() as u64 + POINTERS_LEN;
+
+        if index_offset < data_start || index_offset.saturating_add(index_len as u64) > source_len {
+            return Err(LayerPackError::CorruptIndex("index lies outside the pack".to_string()));
+        }
+        Ok(Self { version, manifest_buf, manifest, index_offset, index_len, data_start })
+    }
+
+    pub(crate) fn index_end(&self) -> u64 {
+        self.index_offset + self.index_len as u64
+    }
+
+    /// Bytes of trailer after the index; only version 3+ packs have one.
+    pub(crate) fn trailer_len(&self, source_len: u64) -> Result<usize> {
+        if self.version < FORMAT_VERSION_TRAILER {
+            return Ok(0);
+        }
+        let trailer_len = source_len - self.index_end();
+        if trailer_len > MAX_TRAILER_LEN {
+            return Err(LayerPackError::CorruptIndex("trailer is too large".to_string()));
+        }
+        Ok(trailer_len as usize)
+    }
+}
+
+pub(crate) struct PackState {
+    /// Sorted by path, for ordered listings and prefix queries.
+    pub entries: BTreeMap<String, FileEntry>,
+    /// Case-folded path -> stored path, present in case-insensitive mode.
+    pub folded: Option<BTreeMap<String, String>>,
+    /// Paths that appeared more than once in the index; only the last one is kept.
+    pub duplicates: Vec<String>,
+    pub layout: PackLayout,
+    pub version: u32,
+    pub verify_policy: VerifyPolicy,
+    /// Paths already checked under `VerifyPolicy::FirstRead`.
+    pub verified: Mutex<HashSet<String>>,
+    kdf: Option<Kdf>,
+    decoder: EntryDecoder,
+    auth_tag: Option<AuthTag>,
+    auth_digest: [u8; 32],
+    pub auth_status: AuthStatus,
+    pub signature: Option<SignatureInfo>,
+    _source_info: String,
+}
+
+impl PackState {
+    /// Decodes the index and trailer and checks the pack-level tag.
+    pub(crate) fn new(
+        header: Header,
+        index_buf: &[u8],
+        trailer_buf: &[u8],
+        source_info: String,
+    ) -> Result<(PackManifest, Self)> {
+        let Header { version, manifest_buf, manifest, index_offset, data_start, .. } = header;
+        let key_id = manifest.key_id().map(str::to_string);
+
+        // 5. Read Index
+        let entry_list = decode_entries(version, index_buf)?;
+
+        // 6. Read Trailer and check the pack-level tag
+        let trailer = if version >= FORMAT_VERSION_TRAILER {
+            let trailer = decode_trailer(trailer_buf)?;
+            // A v3 pack without an auth record has been stripped, not built that way
+            if trailer.auth.is_none() {
+                return Err(LayerPackError::AuthFailed { key_id });
+            }
+            trailer
+        } else {
+            Trailer::default()
+        };
+        let auth_tag = trailer.auth;
+        let digest = auth_digest(version, &manifest_buf, index_offset, index_buf);
+        let signature = trailer.signature.map(|sig| SignatureInfo {
+            signer: sig.public_key,
+            valid: crypto::verify_pack_signature(&sig.public_key, &digest, &sig.signature),
+        });
+        let keyed = manifest.encryption.is_some()
+            || entry_list.iter().any(|e| e.encryption != EncryptionType::None);
+        let auth_status = match auth_tag {
+            // Encryption came with the trailer; a keyed pack without one has
+            // been rewritten as an older version to drop its MAC
+            None if keyed => return Err(LayerPackError::AuthFailed { key_id }),
+            None => AuthStatus::Legacy,
+            // Encrypted packs are always MACed; a bare checksum means the tag was swapped
+            Some(AuthTag::Sha256(tag)) if !keyed && tag == digest => AuthStatus::Checksum,
+            Some(AuthTag::Sha256(_)) => return Err(LayerPackError::AuthFailed { key_id }),
+            Some(AuthTag::HmacSha256(_)) => AuthStatus::Pending,
+        };
+
+        let mut entries = BTreeMap::new();
+        let mut duplicates = Vec::new();
+        for mut entry in entry_list {
+            // Sizes are allocated from on read, so bound them by the pack itself
+            let end = entry.offset.checked_add(entry.compressed_size);
+            if !entry.is_tombstone() && (entry.offset < data_start || end.is_none_or(|end| end > index_offset)) {
+                return Err(LayerPackError::CorruptIndex(format!("'{}' lies outside the data region", entry.path)));
+            }
+            // Current builders store canonical paths; older packs may not
+            if let Cow::Owned(path) = pack_path::normalize(&entry.path) {
+                entry.path = path;
+            }
+            if let Some(previous) = entries.insert(entry.path.clone(), entry) {
+                duplicates.push(previous.path);
+            }
+        }
+
+        let state = Self {
+            entries,
+            folded: None,
+            duplicates,
+            layout: PackLayout { data_start, index_offset },
+            version,
+            verify_policy: VerifyPolicy::default(),
+            verified: Mutex::new(HashSet::new()),
+            kdf: manifest.encryption.as_ref().map(|info| info.kdf.clone()),
+            decoder: EntryDecoder { key_id, key: None, bind_paths: version >= FORMAT_VERSION_ENTRY_AAD },
+            auth_tag,
+            auth_digest: digest,
+            auth_status,
+            signature,
+            _source_info: source_info,
+        };
+        Ok((manifest, state))
+    }
+
+    pub(crate) fn set_key(&mut self, secret: &[u8]) -> Result<()> {
+        let key = derive_pack_key(self.kdf.as_ref(), secret)?;
+        self.install_key(key)
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn kdf(&self) -> Option<&Kdf> {
+        self.kdf.as_ref()
+    }
+
+    /// Checks a key from `derive_pack_key` against the pack MAC and keeps it.
+    pub(crate) fn install_key(&mut self, key: [u8; 32]) -> Result<()> {
+        if let Some(AuthTag::HmacSha256(tag)) = &self.auth_tag {
+            if !crypto::verify_pack_mac(&key, &self.auth_digest, tag) {
+                return Err(LayerPackError::AuthFailed { key_id: self.decoder.key_id.clone() });
+            }
+            self.auth_status = AuthStatus::Authenticated;
+        }
+        self.decoder.key = Some(key);
+        Ok(())
+    }
+
+    pub(crate) fn has_key(&self) -> bool {
+        self.decoder.key.is_some()
+    }
+
+    pub(crate) fn set_verify_policy(&mut self, policy: VerifyPolicy) {
+        self.verify_policy = policy;
+        self.verified.get_mut().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    /// Raises `VerifyPolicy::Never` to `FirstRead`; stricter policies stay.
+    pub(crate) fn require_verification(&mut self) {
+        if self.verify_policy == VerifyPolicy::Never {
+            self.set_verify_policy(VerifyPolicy::FirstRead);
+        }
+    }
+
+    pub(crate) fn verified(&self) -> MutexGuard<'_, HashSet<String>> {
+        self.verified.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Looks up an entry that can be served right now.
+    pub(crate) fn readable_entry(&self, path: &str) -> Result<FileEntry> {
+        if self.auth_status == AuthStatus::Pending {
+            return Err(LayerPackError::MissingKey { key_id: self.decoder.key_id.clone() });
+        }
+        self.lookup(path)
+            .filter(|entry| !entry.is_tombstone())
+            .cloned()
+            .ok_or_else(|| LayerPackError::NotFound(path.to_string()))
+    }
+
+    pub(crate) fn should_verify(&self, entry: &FileEntry) -> bool {
+        // Version 1 packs may carry an empty hash; there is nothing to compare against.
+        !entry.hash.is_empty() && match self.verify_policy {
+            VerifyPolicy::Never => false,
+            VerifyPolicy::Always => true,
+            VerifyPolicy::FirstRead => !self.verified().contains(&entry.path),
+        }
+    }
+
+    /// Owned copy of what decoding needs, for readers and other threads.
+    pub(crate) fn decoder(&self) -> EntryDecoder {
+        self.decoder.clone()
+    }
+
+    pub(crate) fn decrypt_entry<'a>(&self, entry: &FileEntry, raw_data: Cow<'a, [u8]>) -> Result<Cow<'a, [u8]>> {
+        self.decoder.decrypt(entry, raw_data)
+    }
+
+    /// Decrypts and decompresses the stored bytes of `entry`, checking the
+    /// hash if `verify` is set.
+    pub(crate) fn decode_entry<'a>(&self, entry: &FileEntry, raw_data: Cow<'a, [u8]>, verify: bool) -> Result<Cow<'a, [u8]>> {
+        let data = self.decoder.decode(entry, raw_data, verify)?;
+        self.record_verified(entry, verify);
+        Ok(data)
+    }
+
+    /// `FirstRead` bookkeeping after `entry` decoded with its hash checked.
+    pub(crate) fn record_verified(&self, entry: &FileEntry, verify: bool) {
+        if verify && self.verify_policy == VerifyPolicy::FirstRead {
+            self.verified().insert(entry.path.clone());
+        }
+    }
+}
+
+/// Pack key and key ID: everything decoding an entry needs from its pack.
+#[derive(Clone, Default)]
+pub(crate) struct EntryDecoder {
+    /// Copied from the manifest for error messages.
+    key_id: Option<String>,
+    /// Derived AES-256 key, if one was supplied.
+    key: Option<[u8; 32]>,
+    /// Whether entries were sealed with their path as associated data.
+    bind_paths: bool,
+}
+
+impl EntryDecoder {
+    /// Decrypts an entry sealed as one message. Entries that seal each block
+    /// are returned as they are; their blocks go through `decrypt_block`.
+    pub(crate) fn decrypt<'a>(&self, entry: &FileEntry, raw_data: Cow<'a, [u8]>) -> Result<Cow<'a, [u8]>> {
+        // --- ระบบตรวจสอบและถอดรหัส (Backward Compatible) ---
+        match entry.encryption {
+            _ if entry.seals_blocks() => Ok(raw_data),
+            EncryptionType::Aes256Gcm => self.open(entry, None, &raw_data).map(Cow::Owned),
+            EncryptionType::None => {
+                // ถ้าไม่ได้เข้ารหัส (ไฟล์เก่า) ให้ใช้ข้อมูลดิบเลย
+                Ok(raw_data)
+            }
+        }
+    }
+
+    /// Decrypts stored block `index` of a `ZstdChunked` entry; blocks of
+    /// unencrypted entries are returned as they are.
+    pub(crate) fn decrypt_block<'a>(&self, entry: &FileEntry, index: usize, raw_block: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        match entry.seals_blocks() {
+            true => self.open(entry, Some(index), raw_block).map(Cow::Owned),
+            false => Ok(Cow::Borrowed(raw_block)),
+        }
+    }
+
+    fn open(&self, entry: &FileEntry, block: Option<usize>, sealed: &[u8]) -> Result<Vec<u8>> {
+        let key_id = || self.key_id.clone();
+        let key = self.key.as_ref()
+            .ok_or_else(|| LayerPackError::MissingKey { key_id: key_id() })?;
+        // Packs before version 4 sealed entries without associated data
+        let aad = match self.bind_paths {
+            true => crypto::entry_aad(&entry.path, block),
+            false => Vec::new(),
+        };
+        crypto::decrypt(key, sealed, &aad)
+            .map_err(|_| LayerPackError::DecryptFailed { key_id: key_id() })
+    }
+
+    /// Decrypts and decompresses the stored bytes of `entry`, checking the
+    /// hash if `verify` is set. `FirstRead` bookkeeping is left to the caller.
+    pub(crate) fn decode<'a>(&self, entry: &FileEntry, raw_data: Cow<'a, [u8]>, verify: bool) -> Result<Cow<'a, [u8]>> {
+        let decrypted_data = self.decrypt(entry, raw_data)?;
+
+        let data = match entry.compression {
+            CompressionType::Store => decrypted_data,
+            CompressionType::Zstd => Cow::Owned(
+                zstd::stream::decode_all(&decrypted_data[..])
+                    .map_err(|e| LayerPackError::Decompress(format!("Zstd: {}", e)))?
+            ),
+            CompressionType::Lz4 => Cow::Owned(
+                lz4_flex::decompress_size_prepended(&decrypted_data)
+                    .map_err(|e| LayerPackError::Decompress(format!("LZ4: {}", e)))?
+            ),
+            CompressionType::Lz4Frame => {
+                // The index is untrusted: grow as frames decode rather than
+                // pre-sizing from `original_size`, and stop one byte past it
+                let mut decoded = Vec::new();
+                lz4_flex::frame::FrameDecoder::new(&decrypted_data[..])
+                    .take(entry.original_size.saturating_add(1))
+                    .read_to_end(&mut decoded)
+                    .map_err(|e| LayerPackError::Decompress(format!("LZ4 frame: {}", e)))?;
+                if decoded.len() as u64 != entry.original_size {
+                    return Err(LayerPackError::Decompress(format!(
+                        "LZ4 frame: decoded {} bytes, index says {}", decoded.len(), entry.original_size
+                    )));
+                }
+                Cow::Owned(decoded)
+            },
+            CompressionType::ZstdChunked => Cow::Owned(
+                chunked::decode_all(&decrypted_data, entry.original_size, |index, block| self.decrypt_block(entry, index, block))?
+            ),
+        };
+
+        if verify {
+            let actual = hex::encode(sha2::Sha256::digest(&data));
+            check_hash(&entry.path, &entry.hash, actual)?;
+        }
+
+        Ok(data)
+    }
+}
+
+/// Derives the AES key for `secret` under a pack's KDF (see
+/// `PackState::kdf`). Argon2id is slow by design, so this is kept apart from
+/// `install_key` for callers that run it off the current thread.
+pub(crate) fn derive_pack_key(kdf: Option<&Kdf>, secret: &[u8]) -> Result<[u8; 32]> {
+    match kdf {
+        Some(kdf) => crypto::derive_key_with(kdf, secret),
+        None => Ok(crypto::derive_key(secret)),
+    }
+}
+
+fn decode_entries(version: u32, index_buf: &[u8]) -> Result<Vec<FileEntry>> {
+    match version {
+        FORMAT_VERSION_JSON_INDEX => serde_json::from_slice(index_buf)
+            .map_err(|e| LayerPackError::CorruptIndex(e.to_string())),
+        FORMAT_VERSION_BINARY_INDEX | FORMAT_VERSION_TRAILER | FORMAT_VERSION_ENTRY_AAD => decode_index(index_buf),
+        other => Err(LayerPackError::unsupported_version(other)),
+    }
+}
+
+pub(crate) fn check_hash(path: &str, expected: &str, actual: String) -> Result<()> {
+    if actual.eq_ignore_ascii_case(expected) {
+        return Ok(());
+    }
+    Err(LayerPackError::HashMismatch {
+        path: path.to_string(),
+        expected: expected.to_string(),
+        actual,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::index::encode_index;
+    use crate::format::trailer::encode_trailer;
+
+    const SALT: &str = "000102030405060708090a0b0c0d0e0f";
+    const SECRET: &[u8] = b"release key";
+    const DATA: &[u8] = b"{}";
+
+    fn manifest(encrypted: bool) -> String {
+        let encryption = if encrypted {
+            format!(r#","encryption":{{"key_id":"r1","kdf":{{"alg":"hkdf","salt":"{SALT}","info":"layerpack"}}}}"#)
+        } else {
+            String::new()
+        };
+        format!(r#"{{"name":"mod","type":"mod","lang":null,"priority":5,"description":null,"version":"1.0"{encryption}}}"#)
+    }
+
+    fn entry(encryption: EncryptionType) -> FileEntry {
+        FileEntry {
+            path: "data/Actors.json".to_string(),
+            offset: 0,
+            original_size: DATA.len() as u64,
+            compressed_size: DATA.len() as u64,
+            compression: CompressionType::Store,
+            encryption,
+            hash: String::new(),
+            kind: Default::default(),
+        }
+    }
+
+    fn pack_key() -> [u8; 32] {
+        let kdf = Kdf::Hkdf { salt: SALT.to_string(), info: "layerpack".to_string() };
+        derive_pack_key(Some(&kdf), SECRET).unwrap()
+    }
+
+    /// Header, manifest, `DATA` for every non-tombstone entry at offset 0,
+    /// the index, then the trailer `auth` makes from the pack digest (version 3 only).
+    fn build(version: u32, manifest: &str, entries: &[FileEntry], auth: impl Fn(&[u8; 32]) -> Option<AuthTag>) -> Vec<u8> {
+        let data_start = (PREFIX_LEN + manifest.len()) as u64 + POINTERS_LEN;
+        let mut data = Vec::new();
+        let entries: Vec<FileEntry> = entries.iter().cloned().map(|mut entry| {
+            if entry.offset == 0 && !entry.is_tombstone() {
+                entry.offset = data_start + data.len() as u64;
+                data.extend_from_slice(DATA);
+            }
+            entry
+        }).collect();
+        let index = match version {
+            FORMAT_VERSION_JSON_INDEX => serde_json::to_vec(&entries).unwrap(),
+            _ => encode_index(&entries).unwrap(),
+        };
+        let index_offset = data_start + data.len() as u64;
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&version.to_le_bytes());
+        out.extend_from_slice(&(manifest.len() as u32).to_le_bytes());
+        out.extend_from_slice(manifest.as_bytes());
+        out.extend_from_slice(&index_offset.to_le_bytes());
+        out.extend_from_slice(&(index.len() as u32).to_le_bytes());
+        out.extend_from_slice(&data);
+        out.extend_from_slice(&index);
+        if version >= FORMAT_VERSION_TRAILER {
+            let digest = auth_digest(version, manifest.as_bytes(), index_offset, &index);
+            out.extend_from_slice(&encode_trailer(&Trailer { auth: auth(&digest), signature: None }));
+        }
+        out
+    }
+
+    fn checksum(digest: &[u8; 32]) -> Option<AuthTag> {
+        Some(AuthTag::Sha256(*digest))
+    }
+
+    fn mac(digest: &[u8; 32]) -> Option<AuthTag> {
+        Some(AuthTag::HmacSha256(crypto::pack_mac(&pack_key(), digest)))
+    }
+
+    fn load(bytes: &[u8]) -> Result<PackState> {
+        let len = bytes.len() as u64;
+        let (version, rest_len) = parse_prefix(bytes[..PREFIX_LEN].try_into().unwrap(), len)?;
+        let header = Header::parse(version, bytes[PREFIX_LEN..PREFIX_LEN + rest_len].to_vec(), len)?;
+        let index = bytes[header.index_offset as usize..header.index_end() as usize].to_vec();
+        let trailer = bytes[header.index_end() as usize..].to_vec();
+        PackState::new(header, &index, &trailer, "test".to_string()).map(|(_, state)| state)
+    }
+
+    fn raise_priority(bytes: &mut [u8]) {
+        let at = bytes.windows(12).position(|w| w == b"\"priority\":5").unwrap();
+        bytes[at + 11] = b'9';
+    }
+
+    /// Rewrites a version 3 pack as version 2 and drops its trailer.
+    fn downgrade(mut bytes: Vec<u8>) -> Vec<u8> {
+        let len = bytes.len() as u64;
+        let (version, rest_len) = parse_prefix(bytes[..PREFIX_LEN].try_into().unwrap(), len).unwrap();
+        let header = Header::parse(version, bytes[PREFIX_LEN..PREFIX_LEN + rest_len].to_vec(), len).unwrap();
+        bytes.truncate(header.index_end() as usize);
+        bytes[5..9].copy_from_slice(&FORMAT_VERSION_BINARY_INDEX.to_le_bytes());
+        bytes
+    }
+
+    fn is_auth_failed<T>(result: Result<T>) -> bool {
+        matches!(result, Err(LayerPackError::AuthFailed { .. }))
+    }
+
+    #[test]
+    fn checksum_pack_loads_and_rejects_edits() {
+        let mut bytes = build(FORMAT_VERSION_TRAILER, &manifest(false), &[entry(EncryptionType::None)], checksum);
+        assert_eq!(load(&bytes).unwrap().auth_status, AuthStatus::Checksum);
+
+        raise_priority(&mut bytes);
+        assert!(is_auth_failed(load(&bytes)));
+    }
+
+    #[test]
+    fn rejects_stripped_or_swapped_tags() {
+        let plain = [entry(EncryptionType::None)];
+        let encrypted = [entry(EncryptionType::Aes256Gcm)];
+        assert!(is_auth_failed(load(&build(FORMAT_VERSION_TRAILER, &manifest(false), &plain, |_| None))));
+        // A checksum is never enough for encrypted content
+        assert!(is_auth_failed(load(&build(FORMAT_VERSION_TRAILER, &manifest(true), &encrypted, checksum))));
+        assert!(is_auth_failed(load(&build(FORMAT_VERSION_TRAILER, &manifest(false), &encrypted, checksum))));
+    }
+
+    #[test]
+    fn mac_is_checked_when_the_key_arrives() {
+        let bytes = build(FORMAT_VERSION_TRAILER, &manifest(true), &[entry(EncryptionType::Aes256Gcm)], mac);
+        let mut state = load(&bytes).unwrap();
+        assert_eq!(state.auth_status, AuthStatus::Pending);
+        assert!(matches!(state.readable_entry("data/Actors.json"), Err(LayerPackError::MissingKey { .. })));
+        assert!(is_auth_failed(state.set_key(b"wrong key")));
+        state.set_key(SECRET).unwrap();
+        assert_eq!(state.auth_status, AuthStatus::Authenticated);
+
+        let mut tampered = bytes;
+        raise_priority(&mut tampered);
+        let mut state = load(&tampered).unwrap();
+        assert!(is_auth_failed(state.set_key(SECRET)));
+    }
+
+    #[test]
+    fn rejects_downgraded_encrypted_packs() {
+        let mut bytes = build(FORMAT_VERSION_TRAILER, &manifest(true), &[entry(EncryptionType::Aes256Gcm)], mac);
+        raise_priority(&mut bytes);
+        assert!(is_auth_failed(load(&downgrade(bytes))));
+
+        // Encrypted entries alone are enough, with or without manifest info
+        for version in [FORMAT_VERSION_JSON_INDEX, FORMAT_VERSION_BINARY_INDEX] {
+            let bytes = build(version, &manifest(false), &[entry(EncryptionType::Aes256Gcm)], |_| None);
+            assert!(is_auth_failed(load(&bytes)), "version {version}");
+            let bytes = build(version, &manifest(true), &[entry(EncryptionType::None)], |_| None);
+            assert!(is_auth_failed(load(&bytes)), "version {version}");
+        }
+    }
+
+    /// Without a signature nothing stops this; see `AuthStatus::Checksum`.
+    #[test]
+    fn unencrypted_legacy_packs_still_load() {
+        for version in [FORMAT_VERSION_JSON_INDEX, FORMAT_VERSION_BINARY_INDEX] {
+            let state = load(&build(version, &manifest(false), &[entry(EncryptionType::None)], |_| None)).unwrap();
+            assert_eq!(state.auth_status, AuthStatus::Legacy);
+            assert!(state.readable_entry("data/Actors.json").is_ok());
+        }
+        let downgraded = downgrade(build(FORMAT_VERSION_TRAILER, &manifest(false), &[entry(EncryptionType::None)], checksum));
+        assert_eq!(load(&downgraded).unwrap().auth_status, AuthStatus::Legacy);
+    }
+
+    #[test]
+    fn rejects_entries_outside_the_data_region() {
+        let data_start = (PREFIX_LEN + manifest(false).len()) as u64 + POINTERS_LEN;
+        let spans = [(1, 2), (data_start, 3), (data_start + 1, 2), (data_start, u64::MAX)];
+        for (offset, compressed_size) in spans {
+            let entry = FileEntry { offset, compressed_size, ..entry(EncryptionType::None) };
+            let bytes = build(FORMAT_VERSION_BINARY_INDEX, &manifest(false), &[entry], |_| None);
+            assert!(matches!(load(&bytes), Err(LayerPackError::CorruptIndex(_))), "{offset}+{compressed_size}");
+        }
+        // Tombstones carry no data and point nowhere
+        let tombstone = FileEntry::tombstone("data/Old.json".to_string());
+        let bytes = build(FORMAT_VERSION_BINARY_INDEX, &manifest(false), &[entry(EncryptionType::None), tombstone], |_| None);
+        assert!(load(&bytes).is_ok());
+    }
+}
@@ -0,0 +1,515 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use sha2::{Digest, Sha256};
+use crate::error::{LayerPackError, Result};
+use crate::format::chunked::{decode_block, BlockTable};
+use crate::format::{CompressionType, FileEntry};
+use super::{check_hash, EntryDecoder, PackSource};
+
+/// Streaming view of one entry, returned by `LoadedPack::open_file`.
+///
+/// Data is decompressed as it is read. `Store` and `ZstdChunked` entries seek
+/// directly; other entries seek forward by decoding and discarding, and seek
+/// backward by restarting the decoder. When the pack's `VerifyPolicy` asks
+/// for it, the hash is checked once a sequential pass reaches the end of the
+/// entry; a mismatch is returned as an `io::ErrorKind::InvalidData` error wrapping
+/// `LayerPackError::HashMismatch`.
+pub struct EntryReader<'a> {
+    decoder: Decoder<'a>,
+    entry: FileEntry,
+    /// Key for entries whose blocks are decrypted as they are read.
+    keys: EntryDecoder,
+    pos: u64,
+    verify: bool,
+    /// Hash of bytes `0..pos`; dropped once a seek breaks the sequential pass.
+    hasher: Option<Sha256>,
+    /// `FirstRead` bookkeeping, filled in when verification succeeds.
+    verified: Option<&'a Mutex<HashSet<String>>>,
+}
+
+impl<'a> EntryReader<'a> {
+    pub(crate) fn new(
+        entry: &FileEntry,
+        source: Source<'a>,
+        keys: EntryDecoder,
+        verify: bool,
+        verified: Option<&'a Mutex<HashSet<String>>>,
+    ) -> Result<Self> {
+        Ok(Self {
+            decoder: Decoder::new(entry, &keys, source)?,
+            entry: entry.clone(),
+            keys,
+            pos: 0,
+            verify,
+            hasher: verify.then(Sha256::new),
+            verified,
+        })
+    }
+
+    /// Decoded size of the entry.
+    pub fn len(&self) -> u64 {
+        self.entry.original_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether seeking is a direct jump rather than decode-and-discard.
+    pub fn is_seekable(&self) -> bool {
+        matches!(self.decoder, Decoder::Store(_) | Decoder::Chunked(_))
+    }
+
+    fn restart(&mut self) -> io::Result<()> {
+        let placeholder = Decoder::Store(Source::Memory(Cursor::default()));
+        let mut source = std::mem::replace(&mut self.decoder, placeholder).into_source();
+        source.seek(SeekFrom::Start(0))?;
+        self.decoder = Decoder::new(&self.entry, &self.keys, source)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.pos = 0;
+        self.hasher = self.verify.then(Sha256::new);
+        Ok(())
+    }
+
+    fn skip(&mut self, mut remaining: u64) -> io::Result<()> {
+        let mut buf = [0u8; 8192];
+        while remaining > 0 {
+            let want = remaining.min(buf.len() as u64) as usize;
+            let n = self.read(&mut buf[..want])?;
+            if n == 0 {
+                break;
+            }
+            remaining -= n as u64;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.pos < self.len() {
+            let msg = format!("'{}' ended after {} of {} bytes", self.entry.path, self.pos, self.len());
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, LayerPackError::Decompress(msg)));
+        }
+        if let Some(hasher) = self.hasher.take() {
+            let actual = hex::encode(hasher.finalize());
+            check_hash(&self.entry.path, &self.entry.hash, actual)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if let Some(verified) = self.verified {
+                verified.lock().unwrap_or_else(|e| e.into_inner()).insert(self.entry.path.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for EntryReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.decoder.read(buf)?;
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(&buf[..n]);
+        }
+        self.pos += n as u64;
+        if n == 0 && !buf.is_empty() {
+            self.finish()?;
+        }
+        Ok(n)
+    }
+}
+
+impl Seek for EntryReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(delta) => self.len().checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before start of entry"))?;
+        if target == self.pos {
+            return Ok(target);
+        }
+
+        if self.is_seekable() {
+            self.decoder.seek_to(target)?;
+            self.pos = target;
+            // Only a pass that starts at zero can still be verified
+            self.hasher = (self.verify && target == 0).then(Sha256::new);
+            return Ok(target);
+        }
+
+        if target < self.pos {
+            self.restart()?;
+        }
+        // Decoding forward keeps the running hash intact
+        self.skip(target - self.pos)?;
+        Ok(self.pos)
+    }
+}
+
+/// Raw bytes of one entry: a window of the pack, or a buffer when the entry
+/// had to be decrypted up front.
+pub(crate) enum Source<'a> {
+    Pack(Section<'a>),
+    Memory(Cursor<Vec<u8>>),
+}
+
+impl Read for Source<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Pack(section) => section.read(buf),
+            Self::Memory(cursor) => cursor.read(buf),
+        }
+    }
+}
+
+impl Seek for Source<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::Pack(section) => section.seek(pos),
+            Self::Memory(cursor) => cursor.seek(pos),
+        }
+    }
+}
+
+/// `len` bytes of the pack starting at `start`.
+pub(crate) struct Section<'a> {
+    source: &'a dyn PackSource,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl<'a> Section<'a> {
+    pub(crate) fn new(source: &'a dyn PackSource, start: u64, len: u64) -> Self {
+        Self { source, start, len, pos: 0 }
+    }
+}
+
+impl Read for Section<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.pos);
+        let want = remaining.min(buf.len() as u64) as usize;
+        if want == 0 {
+            return Ok(0);
+        }
+        self.source.read_exact_at(&mut buf[..want], self.start + self.pos)?;
+        self.pos += want as u64;
+        Ok(want)
+    }
+}
+
+impl Seek for Section<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before start of entry"))?;
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+enum Decoder<'a> {
+    Store(Source<'a>),
+    Zstd(zstd::stream::read::Decoder<'static, BufReader<Source<'a>>>),
+    Lz4Frame(lz4_flex::frame::FrameDecoder<Source<'a>>),
+    Chunked(ChunkedDecoder<'a>),
+}
+
+impl<'a> Decoder<'a> {
+    fn new(entry: &FileEntry, keys: &EntryDecoder, mut source: Source<'a>) -> Result<Self> {
+        Ok(match entry.compression {
+            CompressionType::ZstdChunked => {
+                let payload_len = source.seek(SeekFrom::End(0))?;
+                source.seek(SeekFrom::Start(0))?;
+                let table = BlockTable::read_from(&mut source, entry.original_size, payload_len)?;
+                Self::Chunked(ChunkedDecoder {
+                    source,
+                    table,
+                    entry: entry.clone(),
+                    keys: keys.clone(),
+                    pos: 0,
+                    current: None,
+                })
+            },
+            CompressionType::Store => Self::Store(source),
+            CompressionType::Zstd => Self::Zstd(zstd::stream::read::Decoder::new(source)?),
+            CompressionType::Lz4Frame => Self::Lz4Frame(lz4_flex::frame::FrameDecoder::new(source)),
+            // The block format is not streamable; decode it whole and serve it from memory
+            CompressionType::Lz4 => {
+                let mut raw = Vec::new();
+                source.read_to_end(&mut raw)?;
+                let data = lz4_flex::decompress_size_prepended(&raw)
+                    .map_err(|e| LayerPackError::Decompress(format!("LZ4: {}", e)))?;
+                Self::Store(Source::Memory(Cursor::new(data)))
+            },
+        })
+    }
+
+    fn into_source(self) -> Source<'a> {
+        match self {
+            Self::Store(source) => source,
+            Self::Zstd(decoder) => decoder.finish().into_inner(),
+            Self::Lz4Frame(decoder) => decoder.into_inner(),
+            Self::Chunked(decoder) => decoder.source,
+        }
+    }
+
+    /// Moves a seekable decoder to `target`.
+    fn seek_to(&mut self, target: u64) -> io::Result<()> {
+        match self {
+            Self::Store(source) => {
+                source.seek(SeekFrom::Start(target))?;
+            },
+            // The block is fetched on the next read
+            Self::Chunked(decoder) => decoder.pos = target,
+            _ => unreachable!("seek_to on a sequential decoder"),
+        }
+        Ok(())
+    }
+}
+
+impl Read for Decoder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Store(source) => source.read(buf),
+            Self::Zstd(decoder) => decoder.read(buf),
+            Self::Lz4Frame(decoder) => decoder.read(buf),
+            Self::Chunked(decoder) => decoder.read(buf),
+        }
+    }
+}
+
+struct ChunkedDecoder<'a> {
+    source: Source<'a>,
+    table: BlockTable,
+    entry: FileEntry,
+    keys: EntryDecoder,
+    pos: u64,
+    /// Index and decoded bytes of the block last read.
+    current: Option<(usize, Vec<u8>)>,
+}
+
+impl ChunkedDecoder<'_> {
+    fn load_block(&mut self, index: usize) -> io::Result<()> {
+        let (offset, len) = self.table.block_span(index);
+        self.source.seek(SeekFrom::Start(offset))?;
+        let mut raw = vec![0u8; len];
+        self.source.read_exact(&mut raw)?;
+
+        let block_size = self.table.block_size;
+        let start = index as u64 * block_size as u64;
+        let expected = (self.entry.original_size - start).min(block_size as u64) as usize;
+        let block = self.keys.decrypt_block(&self.entry, index, &raw)
+            .and_then(|raw| decode_block(&raw, block_size))
+            .and_then(|block| match block.len() == expected {
+                true => Ok(block),
+                false => Err(LayerPackError::Decompress(format!(
+                    "Chunked entry: block {} holds {} bytes, expected {}", index, block.len(), expected
+                ))),
+            })
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.current = Some((index, block));
+        Ok(())
+    }
+}
+
+impl Read for ChunkedDecoder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.entry.original_size || buf.is_empty() {
+            return Ok(0);
+        }
+        let index = self.table.block_for(self.pos);
+        if self.current.as_ref().map(|(i, _)| *i) != Some(index) {
+            self.load_block(index)?;
+        }
+        let Some((_, block)) = &self.current else { unreachable!() };
+        let within = (self.pos - index as u64 * self.table.block_size as u64) as usize;
+        let n = buf.len().min(block.len() - within);
+        buf[..n].copy_from_slice(&block[within..within + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::EncryptionType;
+
+    const BLOCK_SIZE: u32 = 4096;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    /// `ZstdChunked` payload with one block per chunk, each chunk meant to
+    /// hold `BLOCK_SIZE` bytes (the last may be shorter).
+    fn chunked(chunks: &[&[u8]]) -> Vec<u8> {
+        let mut table = BlockTable::new(BLOCK_SIZE, chunks.len() as u64 * BLOCK_SIZE as u64);
+        let mut blocks = Vec::new();
+        for (len, chunk) in table.lengths.iter_mut().zip(chunks) {
+            let block = zstd::bulk::compress(chunk, 3).unwrap();
+            *len = block.len() as u32;
+            blocks.extend_from_slice(&block);
+        }
+        let mut payload = Vec::new();
+        table.write_to(&mut payload).unwrap();
+        payload.extend_from_slice(&blocks);
+        payload
+    }
+
+    fn entry(compression: CompressionType, data: &[u8]) -> FileEntry {
+        FileEntry {
+            path: "movies/intro.webm".to_string(),
+            offset: 0,
+            original_size: data.len() as u64,
+            compressed_size: 0,
+            compression,
+            encryption: EncryptionType::None,
+            hash: hex::encode(Sha256::digest(data)),
+            kind: Default::default(),
+        }
+    }
+
+    fn reader(entry: &FileEntry, stored: Vec<u8>, verify: bool) -> Result<EntryReader<'static>> {
+        EntryReader::new(entry, Source::Memory(Cursor::new(stored)), EntryDecoder::default(), verify, None)
+    }
+
+    /// Verifying reader that records a successful check in `verified`.
+    fn first_read<'a>(entry: &FileEntry, stored: Vec<u8>, verified: &'a Mutex<HashSet<String>>) -> EntryReader<'a> {
+        let source = Source::Memory(Cursor::new(stored));
+        EntryReader::new(entry, source, EntryDecoder::default(), true, Some(verified)).unwrap()
+    }
+
+    fn zstd_entry(data: &[u8]) -> (FileEntry, Vec<u8>) {
+        (entry(CompressionType::Zstd, data), zstd::stream::encode_all(data, 3).unwrap())
+    }
+
+    fn chunked_reader(data: &[u8]) -> EntryReader<'static> {
+        let chunks: Vec<&[u8]> = data.chunks(BLOCK_SIZE as usize).collect();
+        reader(&entry(CompressionType::ZstdChunked, data), chunked(&chunks), true).unwrap()
+    }
+
+    #[test]
+    fn chunked_reads_whole_entry_and_verifies() {
+        let data = sample(2 * BLOCK_SIZE as usize + 100);
+        let mut reader = chunked_reader(&data);
+        assert!(reader.is_seekable());
+        let mut decoded = Vec::new();
+        reader.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn chunked_reads_a_range_across_a_block_boundary() {
+        let data = sample(3 * BLOCK_SIZE as usize);
+        let mut reader = chunked_reader(&data);
+        let start = 2 * BLOCK_SIZE as usize - 10;
+        reader.seek(SeekFrom::Start(start as u64)).unwrap();
+        let mut range = [0u8; 20];
+        reader.read_exact(&mut range).unwrap();
+        assert_eq!(range, data[start..start + 20]);
+
+        // And back into the first block
+        reader.seek(SeekFrom::Start(5)).unwrap();
+        reader.read_exact(&mut range).unwrap();
+        assert_eq!(range, data[5..25]);
+    }
+
+    #[test]
+    fn chunked_rejects_block_of_wrong_size() {
+        let data = sample(2 * BLOCK_SIZE as usize);
+        let short = &data[..BLOCK_SIZE as usize - 1];
+        let payload = chunked(&[short, &data[BLOCK_SIZE as usize..]]);
+        let mut reader = reader(&entry(CompressionType::ZstdChunked, &data), payload, false).unwrap();
+        let err = reader.read(&mut [0u8; 16]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn chunked_rejects_bad_table_on_open() {
+        let data = sample(2 * BLOCK_SIZE as usize);
+        let chunks: Vec<&[u8]> = data.chunks(BLOCK_SIZE as usize).collect();
+        let mut payload = chunked(&chunks);
+        payload[..4].copy_from_slice(&0u32.to_le_bytes());
+        let entry = entry(CompressionType::ZstdChunked, &data);
+        assert!(matches!(reader(&entry, payload, false), Err(LayerPackError::Decompress(_))));
+    }
+
+    #[test]
+    fn zstd_seeks_backward_by_restarting() {
+        let data = sample(20_000);
+        let (entry, stored) = zstd_entry(&data);
+        let mut reader = reader(&entry, stored, true).unwrap();
+        assert!(!reader.is_seekable());
+
+        let mut head = [0u8; 15_000];
+        reader.read_exact(&mut head).unwrap();
+        assert_eq!(reader.seek(SeekFrom::Current(-14_990)).unwrap(), 10);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, data[10..]);
+    }
+
+    #[test]
+    fn forward_seek_keeps_the_running_hash() {
+        let data = sample(20_000);
+        let (entry, stored) = zstd_entry(&data);
+        let verified = Mutex::new(HashSet::new());
+        let mut reader = first_read(&entry, stored, &verified);
+
+        reader.seek(SeekFrom::Start(12_345)).unwrap();
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, data[12_345..]);
+        assert!(verified.lock().unwrap().contains(&entry.path));
+    }
+
+    #[test]
+    fn direct_seek_skips_first_read_bookkeeping() {
+        let data = sample(1000);
+        let entry = entry(CompressionType::Store, &data);
+        let verified = Mutex::new(HashSet::new());
+
+        let mut reader = first_read(&entry, data.clone(), &verified);
+        reader.seek(SeekFrom::Start(10)).unwrap();
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        assert!(verified.lock().unwrap().is_empty());
+
+        // Seeking back to the start makes the next pass checkable again
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        assert!(verified.lock().unwrap().contains(&entry.path));
+    }
+
+    #[test]
+    fn hash_mismatch_is_invalid_data() {
+        let data = sample(1000);
+        let (mut entry, stored) = zstd_entry(&data);
+        entry.hash = hex::encode(Sha256::digest(b"something else"));
+        let verified = Mutex::new(HashSet::new());
+        let mut reader = first_read(&entry, stored, &verified);
+
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let inner = err.into_inner().unwrap().downcast::<LayerPackError>().unwrap();
+        assert!(matches!(*inner, LayerPackError::HashMismatch { .. }));
+        assert!(verified.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn short_entry_is_unexpected_eof() {
+        let data = sample(1000);
+        for compression in [CompressionType::Store, CompressionType::Zstd] {
+            let (mut entry, stored) = match compression {
+                CompressionType::Zstd => zstd_entry(&data),
+                _ => (entry(compression, &data), data.clone()),
+            };
+            entry.original_size += 5;
+            let err = reader(&entry, stored, false).unwrap().read_to_end(&mut Vec::new()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof, "{compression:?}");
+        }
+    }
+}
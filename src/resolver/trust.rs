@@ -0,0 +1,56 @@
+use crate::error::{LayerPackError, Result};
+use super::{LoadedPack, SignatureInfo};
+
+/// Which packs a `Resolver` accepts, based on publisher signatures.
+///
+/// Packs at or below `max_unsigned_priority` may be unsigned (e.g. community
+/// mods sitting under the official layers). Anything above it must carry a
+/// valid signature from one of the trusted publisher keys.
+#[derive(Debug, Clone)]
+pub struct TrustPolicy {
+    trusted: Vec<[u8; 32]>,
+    max_unsigned_priority: i32,
+}
+
+impl TrustPolicy {
+    pub fn new(max_unsigned_priority: i32) -> Self {
+        Self { trusted: Vec::new(), max_unsigned_priority }
+    }
+
+    /// Adds an Ed25519 publisher key.
+    pub fn trust(mut self, public_key: [u8; 32]) -> Self {
+        self.trusted.push(public_key);
+        self
+    }
+
+    pub fn is_trusted(&self, public_key: &[u8; 32]) -> bool {
+        self.trusted.contains(public_key)
+    }
+
+    pub fn max_unsigned_priority(&self) -> i32 {
+        self.max_unsigned_priority
+    }
+
+    /// Checks `pack` as if it sat at `priority` in the layer stack.
+    pub fn check(&self, pack: &LoadedPack, priority: i32) -> Result<()> {
+        self.check_signature(&pack.manifest.name, pack.signature(), priority)
+    }
+
+    pub(crate) fn check_signature(&self, name: &str, signature: Option<SignatureInfo>, priority: i32) -> Result<()> {
+        if priority <= self.max_unsigned_priority {
+            return Ok(());
+        }
+        let reason = match signature {
+            None => "pack is unsigned".to_string(),
+            Some(sig) if !sig.valid => "signature is invalid".to_string(),
+            Some(sig) if !self.is_trusted(&sig.signer) => {
+                format!("signer {} is not trusted", hex::encode(sig.signer))
+            }
+            Some(_) => return Ok(()),
+        };
+        Err(LayerPackError::Untrusted {
+            pack: name.to_string(),
+            reason: format!("{} (priority {} is above {})", reason, priority, self.max_unsigned_priority),
+        })
+    }
+}
@@ -1,5 +1,14 @@
 use wasm_bindgen::prelude::*;
 use layer_pack::resolver::LoadedPack;
+use layer_pack::crypto::StaticKey;
+use layer_pack::LayerPackError;
+
+// สร้าง JS Error ที่มี `code` คงที่ (เช่น "LPACK_NOT_FOUND") ให้ฝั่ง JS ตรวจสอบได้
+fn to_js_error(context: &str, e: LayerPackError) -> JsValue {
+    let err = js_sys::Error::new(&format!("{}: {}", context, e));
+    let _ = js_sys::Reflect::set(&err, &JsValue::from_str("code"), &JsValue::from_str(e.code_name()));
+    err.into()
+}
 
 #[wasm_bindgen]
 pub struct WasmLayerPack {
@@ -9,11 +18,15 @@ pub struct WasmLayerPack {
 #[wasm_bindgen]
 impl WasmLayerPack {
     // Constructor: รับข้อมูลเป็น Uint8Array (หรือ Vec<u8> ใน Rust)
+    // decryption_key: ถ้าไม่ส่งมาจะใช้คีย์ที่ฝังไว้ตอน build
     #[wasm_bindgen(constructor)]
-    pub fn new(data: Vec<u8>) -> Result<WasmLayerPack, JsValue> {
+    pub fn new(data: Vec<u8>, decryption_key: Option<String>) -> Result<WasmLayerPack, JsValue> {
         // ใช้ load_from_memory ที่เราเพิ่งเพิ่มใน Core
-        let pack = LoadedPack::load_from_memory(data)
-            .map_err(|e| JsValue::from_str(&format!("Failed to load pack: {}", e)))?;
+        let pack = match decryption_key {
+            Some(secret) => LoadedPack::load_from_memory_with_key_provider(data, Some(&StaticKey::new(secret))),
+            None => LoadedPack::load_from_memory(data),
+        }
+        .map_err(|e| to_js_error("Failed to load pack", e))?;
         
         Ok(WasmLayerPack { inner: pack })
     }
@@ -34,8 +47,8 @@ impl WasmLayerPack {
     }
 
     #[wasm_bindgen]
-    pub fn read_file(&mut self, path: String) -> Result<Vec<u8>, JsValue> {
+    pub fn read_file(&self, path: String) -> Result<Vec<u8>, JsValue> {
         self.inner.read_file(&path)
-            .map_err(|e| JsValue::from_str(&format!("Failed to read file: {}", e)))
+            .map_err(|e| to_js_error("Failed to read file", e))
     }
 }